@@ -9,4 +9,10 @@ impl APU {
 
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cpu::CPU;
+use crate::mmc::MMC;
+use crate::state::StateError;
+
+// Best Effort Save State, the block format shared by SameBoy and other emulators.
+// A file is made of raw memory dumps followed by blocks (`id: [u8; 4], size: u32, data`)
+// and a footer with the offset of the first block and "BESS". All values are little endian.
+const FOOTER: &[u8; 4] = b"BESS";
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+const CORE_SIZE: usize = 0xD0;
+const RTC_SIZE: usize = 0x30;
+// DMG sizes of the buffers referenced by CORE.
+const WRAM_SIZE: usize = 0x2000;
+const VRAM_SIZE: usize = 0x2000;
+
+struct Blocks {
+    buf: Vec<u8>,
+}
+
+impl Blocks {
+    fn block(&mut self, id: &[u8; 4], data: &[u8]) {
+        self.buf.extend_from_slice(id);
+        self.buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(data);
+    }
+}
+
+pub fn export(cpu: &CPU, mmc: &MMC) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    // Raw dumps referenced by (size, offset) pairs in CORE.
+    let mut buffers = Vec::new();
+    let ram = mmc.rom.mapper.ram().to_vec();
+    let dumps: [&[u8]; 5] = [&mmc.wram[..WRAM_SIZE], &mmc.ppu.vram()[..VRAM_SIZE], &ram, mmc.ppu.oam(), &mmc.hram];
+    for dump in dumps.iter() {
+        buffers.push((dump.len() as u32, buf.len() as u32));
+        buf.extend_from_slice(dump);
+    }
+    // No CGB palettes.
+    buffers.push((0, 0));
+    buffers.push((0, 0));
+
+    let first_block = buf.len() as u32;
+    let mut blocks = Blocks { buf };
+
+    blocks.block(b"NAME", concat!("deepboy ", env!("CARGO_PKG_VERSION")).as_bytes());
+
+    // CORE has to follow NAME directly, strict readers reject any other block before it.
+    let mut core = Vec::with_capacity(CORE_SIZE);
+    core.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    core.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+    core.extend_from_slice(b"GD  ");
+    let regs = &cpu.regs;
+    core.extend_from_slice(&regs.pc.to_le_bytes());
+    for (hi, lo) in [(regs.a, regs.f), (regs.b, regs.c), (regs.d, regs.e), (regs.h, regs.l)] {
+        core.extend_from_slice(&[lo, hi]);
+    }
+    core.extend_from_slice(&regs.sp.to_le_bytes());
+    core.push(cpu.ime as u8);
+    core.push(mmc.int_enable);
+    core.push(if cpu.stop { 2 } else if cpu.halt { 1 } else { 0 });
+    core.push(0);
+    for addr in 0xFF00..=0xFF7F {
+        core.push(mmc.read(addr));
+    }
+    for (size, offset) in buffers.iter() {
+        core.extend_from_slice(&size.to_le_bytes());
+        core.extend_from_slice(&offset.to_le_bytes());
+    }
+    blocks.block(b"CORE", &core);
+
+    let mut info = Vec::new();
+    for addr in 0x0134..=0x0143 {
+        info.push(mmc.rom.mapper.read(addr));
+    }
+    info.push(mmc.rom.mapper.read(0x014E));
+    info.push(mmc.rom.mapper.read(0x014F));
+    blocks.block(b"INFO", &info);
+
+    let writes = mmc.rom.mapper.bank_writes();
+    if !writes.is_empty() {
+        let mut mbc = Vec::new();
+        for (addr, dat) in writes.iter() {
+            mbc.extend_from_slice(&addr.to_le_bytes());
+            mbc.push(*dat);
+        }
+        blocks.block(b"MBC ", &mbc);
+    }
+
+    if let Some((current, latched)) = mmc.rom.mapper.rtc() {
+        let mut rtc = Vec::with_capacity(RTC_SIZE);
+        for r in current.iter().chain(latched.iter()) {
+            rtc.extend_from_slice(&u32::from(*r).to_le_bytes());
+        }
+        // The clock doesn't tick, so there is no meaningful timestamp.
+        rtc.extend_from_slice(&0u64.to_le_bytes());
+        blocks.block(b"RTC ", &rtc);
+    }
+
+    blocks.block(b"END ", &[]);
+
+    let mut buf = blocks.buf;
+    buf.extend_from_slice(&first_block.to_le_bytes());
+    buf.extend_from_slice(FOOTER);
+    buf
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+// Returns the (size, offset) buffer at `index` in CORE, checked against the file.
+fn buffer<'a>(data: &'a [u8], core: &[u8], index: usize) -> Result<&'a [u8], StateError> {
+    let pos = 0x98 + index * 8;
+    let size = u32_at(core, pos) as usize;
+    let offset = u32_at(core, pos + 4) as usize;
+    data.get(offset..offset + size).ok_or(StateError::Format("buffer out of range"))
+}
+
+// Imports a state written by another emulator. Memory is copied as far as it fits,
+// registers are restored without the side effects of CPU writes.
+pub fn import(cpu: &mut CPU, mmc: &mut MMC, data: &[u8]) -> Result<(), StateError> {
+    if data.len() < 8 || &data[data.len() - 4..] != FOOTER {
+        return Err(StateError::Format("missing BESS footer"));
+    }
+
+    let mut pos = u32_at(data, data.len() - 8) as usize;
+    let mut core = None;
+    let mut info = None;
+    let mut mbc = None;
+    let mut rtc = None;
+    loop {
+        let header = data.get(pos..pos + 8).ok_or(StateError::Format("truncated block"))?;
+        let size = u32_at(header, 4) as usize;
+        let block = data.get(pos + 8..pos + 8 + size).ok_or(StateError::Format("truncated block"))?;
+        match &header[0..4] {
+            b"CORE" => core = Some(block),
+            b"INFO" => info = Some(block),
+            b"MBC " => mbc = Some(block),
+            b"RTC " => rtc = Some(block),
+            b"END " => break,
+            // NAME and blocks of other emulators.
+            _ => {},
+        }
+        pos += 8 + size;
+    }
+
+    let core = core.ok_or(StateError::Format("missing CORE block"))?;
+    if core.len() < CORE_SIZE {
+        return Err(StateError::Format("CORE block too short"));
+    }
+    if u16_at(core, 0) != MAJOR_VERSION {
+        return Err(StateError::Format("unsupported BESS version"));
+    }
+    if core[4] != b'G' {
+        return Err(StateError::Format("only DMG states are supported"));
+    }
+
+    if let Some(info) = info {
+        if info.len() < 0x12 {
+            return Err(StateError::Format("INFO block too short"));
+        }
+        let title: Vec<u8> = (0x0134..=0x0143).map(|addr| mmc.rom.mapper.read(addr)).collect();
+        let checksum = [mmc.rom.mapper.read(0x014E), mmc.rom.mapper.read(0x014F)];
+        if info[0..0x10] != title[..] || info[0x10..0x12] != checksum {
+            return Err(StateError::Format("state was made with another ROM"));
+        }
+    }
+
+    if let Some(mbc) = mbc {
+        if mbc.len() % 3 != 0 {
+            return Err(StateError::Format("invalid MBC block"));
+        }
+        for write in mbc.chunks(3) {
+            let addr = u16_at(write, 0);
+            if addr < 0x8000 {
+                mmc.rom.mapper.write(addr, write[2]);
+            }
+        }
+    }
+
+    if let Some(rtc) = rtc {
+        if rtc.len() < RTC_SIZE {
+            return Err(StateError::Format("RTC block too short"));
+        }
+        let mut current = [0; 5];
+        let mut latched = [0; 5];
+        for i in 0..5 {
+            current[i] = u32_at(rtc, i * 4) as u8;
+            latched[i] = u32_at(rtc, 0x14 + i * 4) as u8;
+        }
+        mmc.rom.mapper.set_rtc(current, latched);
+    }
+
+    let wram = buffer(data, core, 0)?;
+    let vram = buffer(data, core, 1)?;
+    let ram = buffer(data, core, 2)?;
+    let oam = buffer(data, core, 3)?;
+    let hram = buffer(data, core, 4)?;
+    copy(&mut mmc.wram, wram);
+    copy(mmc.ppu.vram_mut(), vram);
+    copy(mmc.rom.mapper.ram_mut(), ram);
+    copy(mmc.ppu.oam_mut(), oam);
+    copy(&mut mmc.hram, hram);
+
+    let io = &core[0x18..0x98];
+    mmc.joypad.write(0xFF00, io[0x00], &mut mmc.int_flag);
+    mmc.serial.set_register(0xFF01, io[0x01]);
+    mmc.serial.set_register(0xFF02, io[0x02]);
+    for addr in 0xFF04..=0xFF07 {
+        mmc.timer.set_register(addr, io[addr as usize - 0xFF00]);
+    }
+    // LCDC first since the other registers depend on it.
+    for addr in [0xFF40, 0xFF41, 0xFF42, 0xFF43, 0xFF44, 0xFF45, 0xFF47, 0xFF48, 0xFF49, 0xFF4A, 0xFF4B] {
+        mmc.ppu.set_register(addr, io[addr as usize - 0xFF00]);
+    }
+    mmc.ppu.v_blank = false;
+    mmc.dma.set_register(io[0x46]);
+    mmc.key1.set(if mmc.rom.cgb { io[0x4D] & 0x81 } else { 0 });
+    mmc.rom.disable_boot_rom = io[0x50];
+    mmc.bank = 0x01;
+    mmc.int_flag.set(io[0x0F]);
+    mmc.int_enable = core[0x15];
+
+    let regs = &mut cpu.regs;
+    regs.pc = u16_at(core, 0x08);
+    let [f, a] = u16_at(core, 0x0A).to_le_bytes();
+    let [c, b] = u16_at(core, 0x0C).to_le_bytes();
+    let [e, d] = u16_at(core, 0x0E).to_le_bytes();
+    let [l, h] = u16_at(core, 0x10).to_le_bytes();
+    regs.a = a;
+    regs.f = f & 0xF0;
+    regs.b = b;
+    regs.c = c;
+    regs.d = d;
+    regs.e = e;
+    regs.h = h;
+    regs.l = l;
+    regs.sp = u16_at(core, 0x12);
+    cpu.ime = core[0x14] != 0;
+    cpu.ei_delay = false;
+    cpu.halt = core[0x16] == 1;
+    cpu.halt_bug = false;
+    cpu.stop = core[0x16] == 2;
+    cpu.locked = false;
+    cpu.event = None;
+
+    Ok(())
+}
+
+fn copy(dst: &mut [u8], src: &[u8]) {
+    let len = dst.len().min(src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+}
@@ -0,0 +1,74 @@
+// Everything the CPU sees: memory-mapped reads and writes, and the rest of
+// the machine advancing after each instruction.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, dat: u8);
+    // Advances the other components by `cycles` T-cycles.
+    fn tick(&mut self, cycles: u32);
+    // IE and IF as the CPU polls and acknowledges them internally.
+    // These aren't memory accesses, so watchpoints don't see them.
+    fn int_enable(&self) -> u8;
+    fn int_flag(&self) -> u8;
+    fn set_int_flag(&mut self, dat: u8);
+
+    // Like `tick`, while STOP holds the divider and the timer in reset.
+    fn tick_stopped(&mut self, cycles: u32) {
+        self.tick(cycles)
+    }
+
+    // CGB speed switch requested through KEY1, performed by STOP.
+    fn speed_switch_armed(&self) -> bool {
+        false
+    }
+
+    fn switch_speed(&mut self) {}
+}
+
+// 64 KiB of plain RAM with nothing else attached, for running the CPU in isolation.
+#[derive(Clone)]
+pub struct FlatBus {
+    pub memory: Vec<u8>,
+    // T-cycles passed to `tick`.
+    pub cycles: u64,
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+        }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        self.memory[addr as usize] = dat;
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += u64::from(cycles);
+    }
+
+    fn int_enable(&self) -> u8 {
+        self.memory[0xFFFF]
+    }
+
+    fn int_flag(&self) -> u8 {
+        self.memory[0xFF0F]
+    }
+
+    fn set_int_flag(&mut self, dat: u8) {
+        self.memory[0xFF0F] = dat;
+    }
+}
@@ -0,0 +1,292 @@
+use std::{env, fmt, fs, io, path::PathBuf};
+use minifb::Key;
+use crate::joypad::Button;
+use crate::limiter::Speed;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Pause,
+    FrameAdvance,
+    Rewind,
+    Reset,
+    FastForward,
+    SlowMotion,
+    SaveState,
+    LoadState,
+    NextSlot,
+    PreviousSlot,
+    Screenshot,
+    Debug,
+    Quit,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, text: String },
+    UnknownAction { line: usize, name: String },
+    UnknownKey { line: usize, name: String },
+    InvalidValue { line: usize, name: String, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Syntax { line, text } => write!(f, "line {}: expected `action = key`, found `{}`", line, text),
+            ConfigError::UnknownAction { line, name } => write!(f, "line {}: unknown action `{}`", line, name),
+            ConfigError::UnknownKey { line, name } => write!(f, "line {}: unknown key `{}`", line, name),
+            ConfigError::InvalidValue { line, name, value } => write!(f, "line {}: invalid value `{}` for `{}`", line, value, name),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+enum Action {
+    Button(Button),
+    Turbo(Button),
+    Hotkey(Hotkey),
+}
+
+// User settings read from `~/.config/deepboy/config`.
+//
+// Each line binds an action to one or more keys, e.g. `start = Enter` or `a = A, Z`.
+// Lines starting with `#` are comments. Unlisted actions keep their default keys.
+// `turbo_rate = 2` sets how many frames turbo buttons stay pressed and released.
+// `fast_forward_speed = 4` (or `uncapped`) and `slow_motion_speed = 0.5` set the speed multipliers.
+// `run_ahead = 1` runs up to 4 frames ahead to hide input lag.
+// `rewind_interval = 4` sets how often a rewind snapshot is taken and `rewind_budget = 64` its memory in MiB.
+pub struct Config {
+    pub buttons: Vec<(Key, Button)>,
+    pub turbo_buttons: Vec<(Key, Button)>,
+    pub turbo_rate: u32,
+    pub fast_forward_speed: Speed,
+    pub slow_motion_speed: Speed,
+    pub run_ahead: u32,
+    pub rewind_interval: u32,
+    // Bytes, 0 disables rewind.
+    pub rewind_budget: usize,
+    pub hotkeys: Vec<(Key, Hotkey)>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+            return Some(PathBuf::from(dir).join("deepboy").join("config"));
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("deepboy").join("config"))
+    }
+
+    // Loads the user config, falling back to the defaults if there is none.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Config::path() {
+            Some(path) if path.exists() => Config::load_from(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn load_from(path: PathBuf) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path, e))?;
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let text = raw.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = match text.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return Err(ConfigError::Syntax { line, text: text.to_string() }),
+            };
+
+            if name.eq_ignore_ascii_case("turbo_rate") {
+                config.turbo_rate = match value.parse::<u32>() {
+                    Ok(rate) if rate > 0 => rate,
+                    _ => return Err(ConfigError::InvalidValue { line, name: name.to_string(), value: value.to_string() }),
+                };
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("run_ahead") {
+                config.run_ahead = match value.parse::<u32>() {
+                    Ok(frames) if frames <= 4 => frames,
+                    _ => return Err(ConfigError::InvalidValue { line, name: name.to_string(), value: value.to_string() }),
+                };
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("rewind_interval") {
+                config.rewind_interval = match value.parse::<u32>() {
+                    Ok(interval) if interval > 0 => interval,
+                    _ => return Err(ConfigError::InvalidValue { line, name: name.to_string(), value: value.to_string() }),
+                };
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("rewind_budget") {
+                config.rewind_budget = match value.parse::<usize>().ok().and_then(|mib| mib.checked_mul(1 << 20)) {
+                    Some(bytes) => bytes,
+                    None => return Err(ConfigError::InvalidValue { line, name: name.to_string(), value: value.to_string() }),
+                };
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("fast_forward_speed") || name.eq_ignore_ascii_case("slow_motion_speed") {
+                let speed = match value.parse::<f64>() {
+                    Ok(factor) if factor > 0.0 => Speed::Factor(factor),
+                    _ if value.eq_ignore_ascii_case("uncapped") => Speed::Uncapped,
+                    _ => return Err(ConfigError::InvalidValue { line, name: name.to_string(), value: value.to_string() }),
+                };
+                if name.eq_ignore_ascii_case("fast_forward_speed") {
+                    config.fast_forward_speed = speed;
+                } else {
+                    config.slow_motion_speed = speed;
+                }
+                continue;
+            }
+
+            let action = parse_action(name)
+                .ok_or_else(|| ConfigError::UnknownAction { line, name: name.to_string() })?;
+
+            let mut keys = Vec::new();
+            for key_name in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                let key = parse_key(key_name)
+                    .ok_or_else(|| ConfigError::UnknownKey { line, name: key_name.to_string() })?;
+                keys.push(key);
+            }
+
+            match action {
+                Action::Button(button) => {
+                    config.buttons.retain(|(_, b)| *b != button);
+                    config.buttons.extend(keys.into_iter().map(|key| (key, button)));
+                }
+                Action::Turbo(button) => {
+                    config.turbo_buttons.retain(|(_, b)| *b != button);
+                    config.turbo_buttons.extend(keys.into_iter().map(|key| (key, button)));
+                }
+                Action::Hotkey(hotkey) => {
+                    config.hotkeys.retain(|(_, h)| *h != hotkey);
+                    config.hotkeys.extend(keys.into_iter().map(|key| (key, hotkey)));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            buttons: vec![
+                (Key::Right, Button::Right),
+                (Key::Left, Button::Left),
+                (Key::Up, Button::Up),
+                (Key::Down, Button::Down),
+                (Key::A, Button::A),
+                (Key::B, Button::B),
+                (Key::Space, Button::Select),
+                (Key::Enter, Button::Start),
+            ],
+            turbo_buttons: vec![
+                (Key::S, Button::A),
+                (Key::X, Button::B),
+            ],
+            turbo_rate: 2,
+            fast_forward_speed: Speed::Uncapped,
+            slow_motion_speed: Speed::Factor(0.5),
+            run_ahead: 0,
+            rewind_interval: 4,
+            rewind_budget: 64 << 20,
+            hotkeys: vec![
+                (Key::P, Hotkey::Pause),
+                (Key::N, Hotkey::FrameAdvance),
+                (Key::Backspace, Hotkey::Rewind),
+                (Key::R, Hotkey::Reset),
+                (Key::Tab, Hotkey::FastForward),
+                (Key::M, Hotkey::SlowMotion),
+                (Key::F5, Hotkey::SaveState),
+                (Key::F8, Hotkey::LoadState),
+                (Key::F7, Hotkey::NextSlot),
+                (Key::F6, Hotkey::PreviousSlot),
+                (Key::F12, Hotkey::Screenshot),
+                (Key::F9, Hotkey::Debug),
+                (Key::Escape, Hotkey::Quit),
+            ],
+        }
+    }
+}
+
+fn parse_action(name: &str) -> Option<Action> {
+    let action = match name.to_ascii_lowercase().as_str() {
+        "a" => Action::Button(Button::A),
+        "b" => Action::Button(Button::B),
+        "select" => Action::Button(Button::Select),
+        "start" => Action::Button(Button::Start),
+        "up" => Action::Button(Button::Up),
+        "down" => Action::Button(Button::Down),
+        "left" => Action::Button(Button::Left),
+        "right" => Action::Button(Button::Right),
+        "turbo_a" => Action::Turbo(Button::A),
+        "turbo_b" => Action::Turbo(Button::B),
+        "pause" => Action::Hotkey(Hotkey::Pause),
+        "frame_advance" => Action::Hotkey(Hotkey::FrameAdvance),
+        "rewind" => Action::Hotkey(Hotkey::Rewind),
+        "reset" => Action::Hotkey(Hotkey::Reset),
+        "fast_forward" => Action::Hotkey(Hotkey::FastForward),
+        "slow_motion" => Action::Hotkey(Hotkey::SlowMotion),
+        "save_state" => Action::Hotkey(Hotkey::SaveState),
+        "load_state" => Action::Hotkey(Hotkey::LoadState),
+        "next_slot" => Action::Hotkey(Hotkey::NextSlot),
+        "previous_slot" => Action::Hotkey(Hotkey::PreviousSlot),
+        "screenshot" => Action::Hotkey(Hotkey::Screenshot),
+        "debug" => Action::Hotkey(Hotkey::Debug),
+        "quit" => Action::Hotkey(Hotkey::Quit),
+        _ => return None,
+    };
+    Some(action)
+}
+
+pub fn parse_key(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "a" => Key::A, "b" => Key::B, "c" => Key::C, "d" => Key::D,
+        "e" => Key::E, "f" => Key::F, "g" => Key::G, "h" => Key::H,
+        "i" => Key::I, "j" => Key::J, "k" => Key::K, "l" => Key::L,
+        "m" => Key::M, "n" => Key::N, "o" => Key::O, "p" => Key::P,
+        "q" => Key::Q, "r" => Key::R, "s" => Key::S, "t" => Key::T,
+        "u" => Key::U, "v" => Key::V, "w" => Key::W, "x" => Key::X,
+        "y" => Key::Y, "z" => Key::Z,
+        "0" => Key::Key0, "1" => Key::Key1, "2" => Key::Key2, "3" => Key::Key3,
+        "4" => Key::Key4, "5" => Key::Key5, "6" => Key::Key6, "7" => Key::Key7,
+        "8" => Key::Key8, "9" => Key::Key9,
+        "f1" => Key::F1, "f2" => Key::F2, "f3" => Key::F3, "f4" => Key::F4,
+        "f5" => Key::F5, "f6" => Key::F6, "f7" => Key::F7, "f8" => Key::F8,
+        "f9" => Key::F9, "f10" => Key::F10, "f11" => Key::F11, "f12" => Key::F12,
+        "up" => Key::Up, "down" => Key::Down, "left" => Key::Left, "right" => Key::Right,
+        "space" => Key::Space, "enter" | "return" => Key::Enter, "escape" | "esc" => Key::Escape,
+        "tab" => Key::Tab, "backspace" => Key::Backspace, "delete" => Key::Delete,
+        "insert" => Key::Insert, "home" => Key::Home, "end" => Key::End,
+        "pageup" => Key::PageUp, "pagedown" => Key::PageDown,
+        "leftshift" => Key::LeftShift, "rightshift" => Key::RightShift,
+        "leftctrl" => Key::LeftCtrl, "rightctrl" => Key::RightCtrl,
+        "leftalt" => Key::LeftAlt, "rightalt" => Key::RightAlt,
+        "comma" => Key::Comma, "period" => Key::Period, "slash" => Key::Slash,
+        "backslash" => Key::Backslash, "semicolon" => Key::Semicolon,
+        "apostrophe" => Key::Apostrophe, "minus" => Key::Minus, "equal" => Key::Equal,
+        "leftbracket" => Key::LeftBracket, "rightbracket" => Key::RightBracket,
+        "backquote" => Key::Backquote,
+        "numpad0" => Key::NumPad0, "numpad1" => Key::NumPad1, "numpad2" => Key::NumPad2,
+        "numpad3" => Key::NumPad3, "numpad4" => Key::NumPad4, "numpad5" => Key::NumPad5,
+        "numpad6" => Key::NumPad6, "numpad7" => Key::NumPad7, "numpad8" => Key::NumPad8,
+        "numpad9" => Key::NumPad9,
+        _ => return None,
+    };
+    Some(key)
+}
@@ -1,6 +1,6 @@
-use std::{cell::RefCell, rc::Rc};
-use crate::mmc::MMC;
+use crate::bus::Bus;
 use crate::register::*;
+use crate::state::{StateError, StateReader, StateWriter};
 use super::register::Register;
 
 pub const CYCLES: [u32; 0x100] = [
@@ -63,37 +63,125 @@ pub const CB_CYCLES: [u32; 0x100] = [
     2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // Fx
 ];
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuEvent {
+    IllegalOpcode { pc: u16, opcode: u8 },
+}
+
+#[derive(Clone)]
 pub struct CPU {
-    pub mmc: Rc<RefCell<MMC>>,
     pub regs: Register,
     pub opcode: u8,
     pub cb_opcode: u8,
     pub halt: bool,
+    pub halt_bug: bool,
+    pub stop: bool,
+    pub locked: bool,
+    pub event: Option<CpuEvent>,
+    // Vector of the interrupt dispatched by the last `run`.
+    pub interrupt: Option<u16>,
+    // M-cycles of the current instruction the bus was already ticked for.
+    ticked: u32,
     pub ime: bool,
     pub ei_delay: bool,
     pub debug: bool,
 }
 
 impl CPU {
-    pub fn new(mmc: Rc<RefCell<MMC>>) -> Self {
+    pub fn new() -> Self {
         CPU {
-            mmc: mmc,
             regs: Register::new(),
             opcode: 0,
             cb_opcode: 0,
             halt: false,
+            halt_bug: false,
+            stop: false,
+            locked: false,
+            event: None,
+            interrupt: None,
+            ticked: 0,
             ime: true,
             ei_delay: false,
             debug: false,
         }
     }
 
-    pub fn set_debug(&mut self) {
-        self.debug = true;
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let regs = &self.regs;
+        for r in [regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] {
+            w.write_u8(r);
+        }
+        w.write_u16(regs.sp);
+        w.write_u16(regs.pc);
+        w.write_u8(self.opcode);
+        w.write_u8(self.cb_opcode);
+        w.write_bool(self.halt);
+        w.write_bool(self.halt_bug);
+        w.write_bool(self.stop);
+        w.write_bool(self.locked);
+        w.write_bool(self.ime);
+        w.write_bool(self.ei_delay);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let regs = &mut self.regs;
+        for reg in [&mut regs.a, &mut regs.f, &mut regs.b, &mut regs.c, &mut regs.d, &mut regs.e, &mut regs.h, &mut regs.l] {
+            *reg = r.read_u8()?;
+        }
+        regs.sp = r.read_u16()?;
+        regs.pc = r.read_u16()?;
+        self.opcode = r.read_u8()?;
+        self.cb_opcode = r.read_u8()?;
+        self.halt = r.read_bool()?;
+        self.halt_bug = r.read_bool()?;
+        self.stop = r.read_bool()?;
+        self.locked = r.read_bool()?;
+        self.ime = r.read_bool()?;
+        self.ei_delay = r.read_bool()?;
+        self.event = None;
+        Ok(())
+    }
+
+    pub fn take_event(&mut self) -> Option<CpuEvent> {
+        self.event.take()
+    }
+
+    // Executes one instruction and ticks the bus for every M-cycle of it. Returns the elapsed T-cycles.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        let cycles = self.run(bus);
+        // Internal M-cycles without a memory access.
+        for _ in self.ticked..cycles {
+            self.tick(bus);
+        }
+        cycles * 4
     }
 
-    pub fn run(&mut self) -> u32 {
-        let hi = self.handle_interrupt();
+    // Executes one instruction. The bus is ticked after each memory access, the remaining
+    // M-cycles are left to the caller. Returns the elapsed M-cycles.
+    pub fn run<B: Bus>(&mut self, bus: &mut B) -> u32 {
+        self.interrupt = None;
+        self.ticked = 0;
+        // An illegal opcode hangs the CPU until reset. Not even interrupts wake it up.
+        if self.locked {
+            return 1;
+        }
+
+        if self.stop {
+            // Stays in low-power mode until one of the selected joypad lines goes low.
+            if self.read8(bus, IoRegs::JOYP as u16) & 0x0F == 0x0F {
+                return 1;
+            }
+            self.stop = false;
+        }
+
+        // EI takes effect after the instruction following it.
+        let ei_delay = self.ei_delay;
+
+        let hi = self.handle_interrupt(bus);
         // println!("cpu next halt:{}", self.halt);
 
         if hi > 0 {
@@ -105,34 +193,57 @@ impl CPU {
         }
 
         if self.debug {
-            self.debug_out();
+            self.debug_out(bus);
         }
 
         let mut cycles = 0;
-        self.opcode = self.imm8();
+        let pc = self.regs.pc;
+        self.opcode = if self.halt_bug {
+            // HALT bug: PC fails to increment after the opcode fetch.
+            self.halt_bug = false;
+            self.read8(bus, self.regs.pc)
+        } else {
+            self.imm8(bus)
+        };
         match self.opcode {
             // NOP
-            0x00 | 0x10 => {},
+            0x00 => {},
+
+            // STOP
+            0x10 => {
+                // The byte after STOP is skipped, and DIV is reset internally, without bus cycles.
+                self.regs.pc = self.regs.pc.wrapping_add(1);
+                if bus.speed_switch_armed() {
+                    bus.switch_speed();
+                } else {
+                    bus.write(IoRegs::DIV as u16, 0);
+                    self.stop = true;
+                }
+            }
 
-            // NONE
-            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {},
+            // ILLEGAL
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                self.locked = true;
+                self.event = Some(CpuEvent::IllegalOpcode { pc, opcode: self.opcode });
+                return 1;
+            }
 
             // JR
             0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
-                let offset = self.imm8();
+                let offset = self.imm8(bus);
                 match self.opcode {
                     0x18 => self.jr(offset),
-                    0x20 => if !self.regs.get_z() { self.jr(offset) },
-                    0x28 => if self.regs.get_z() { self.jr(offset) },
-                    0x30 => if !self.regs.get_c() { self.jr(offset) },
-                    0x38 => if self.regs.get_c() { self.jr(offset) },
+                    0x20 if !self.regs.get_z() => self.jr(offset),
+                    0x28 if self.regs.get_z() => self.jr(offset),
+                    0x30 if !self.regs.get_c() => self.jr(offset),
+                    0x38 if self.regs.get_c() => self.jr(offset),
                     _ => {},
                 }
             }
 
             // LD R16,D16
             0x01 | 0x11 | 0x21 | 0x31 => {
-                let dat = self.imm16();
+                let dat = self.imm16(bus);
                 match self.opcode {
                     0x01 => self.regs.set_bc(dat),
                     0x11 => self.regs.set_de(dat),
@@ -143,16 +254,16 @@ impl CPU {
             }
 
             // LD (R16),A
-            0x02 => self.write8(self.regs.get_bc(), self.regs.a),
-            0x12 => self.write8(self.regs.get_de(), self.regs.a),
+            0x02 => self.write8(bus, self.regs.get_bc(), self.regs.a),
+            0x12 => self.write8(bus, self.regs.get_de(), self.regs.a),
             0x22 => {
                 let addr = self.regs.get_hl();
-                self.write8(addr, self.regs.a);
+                self.write8(bus, addr, self.regs.a);
                 self.regs.set_hl(addr.wrapping_add(1));
             }
             0x32 => {
                 let addr = self.regs.get_hl();
-                self.write8(addr, self.regs.a);
+                self.write8(bus, addr, self.regs.a);
                 self.regs.set_hl(addr.wrapping_sub(1));
             }
 
@@ -168,9 +279,9 @@ impl CPU {
             0x24 => self.regs.h = self.inc(self.regs.h),
             0x34 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let inc_dat = self.inc(dat);
-                self.write8(addr, inc_dat);
+                self.write8(bus, addr, inc_dat);
             }
             0x0C => self.regs.c = self.inc(self.regs.c),
             0x1C => self.regs.e = self.inc(self.regs.e),
@@ -183,9 +294,9 @@ impl CPU {
             0x25 => self.regs.h = self.dec(self.regs.h),
             0x35 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let dec_dat = self.dec(dat);
-                self.write8(addr, dec_dat);
+                self.write8(bus, addr, dec_dat);
             }
             0x0D => self.regs.c = self.dec(self.regs.c),
             0x1D => self.regs.e = self.dec(self.regs.e),
@@ -193,14 +304,14 @@ impl CPU {
             0x3D => self.regs.a = self.dec(self.regs.a),
 
             // LD R8,D8
-            0x06 => self.regs.b = self.imm8(),
-            0x16 => self.regs.d = self.imm8(),
-            0x26 => self.regs.h = self.imm8(),
+            0x06 => self.regs.b = self.imm8(bus),
+            0x16 => self.regs.d = self.imm8(bus),
+            0x26 => self.regs.h = self.imm8(bus),
             // LD (HL),D8
             0x36 => {
                 let addr = self.regs.get_hl();
-                let dat = self.imm8();
-                self.write8(addr, dat);
+                let dat = self.imm8(bus);
+                self.write8(bus, addr, dat);
             },
 
             // RLCA
@@ -257,27 +368,27 @@ impl CPU {
 
             // LD (A16),SP
             0x08 => {
-                let addr = self.imm16();
-                self.write16(addr, self.regs.sp);
+                let addr = self.imm16(bus);
+                self.write16(bus, addr, self.regs.sp);
             },
 
             // LD A,(R16)
             0x0A => {
                 let addr = self.regs.get_bc();
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
             }
             0x1A => {
                 let addr = self.regs.get_de();
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
             }
             0x2A => {
                 let addr = self.regs.get_hl();
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
                 self.regs.set_hl(addr.wrapping_add(1));
             }
             0x3A => {
                 let addr = self.regs.get_hl();
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
                 self.regs.set_hl(addr.wrapping_sub(1));
             }
 
@@ -288,10 +399,10 @@ impl CPU {
             0x3B => self.regs.set_af(self.regs.get_af().wrapping_sub(1)),
 
             // LD R8,D8
-            0x0E => self.regs.c = self.imm8(),
-            0x1E => self.regs.e = self.imm8(),
-            0x2E => self.regs.l = self.imm8(),
-            0x3E => self.regs.a = self.imm8(),
+            0x0E => self.regs.c = self.imm8(bus),
+            0x1E => self.regs.e = self.imm8(bus),
+            0x2E => self.regs.l = self.imm8(bus),
+            0x3E => self.regs.a = self.imm8(bus),
 
             // RRCA
             0x0F => {
@@ -375,16 +486,24 @@ impl CPU {
             0x6F => self.regs.l = self.regs.a,
 
             // LD (HL),R
-            0x70 => self.write8(self.regs.get_hl(), self.regs.b),
-            0x71 => self.write8(self.regs.get_hl(), self.regs.c),
-            0x72 => self.write8(self.regs.get_hl(), self.regs.d),
-            0x73 => self.write8(self.regs.get_hl(), self.regs.e),
-            0x74 => self.write8(self.regs.get_hl(), self.regs.h),
-            0x75 => self.write8(self.regs.get_hl(), self.regs.l),
-            0x77 => self.write8(self.regs.get_hl(), self.regs.a),
+            0x70 => self.write8(bus, self.regs.get_hl(), self.regs.b),
+            0x71 => self.write8(bus, self.regs.get_hl(), self.regs.c),
+            0x72 => self.write8(bus, self.regs.get_hl(), self.regs.d),
+            0x73 => self.write8(bus, self.regs.get_hl(), self.regs.e),
+            0x74 => self.write8(bus, self.regs.get_hl(), self.regs.h),
+            0x75 => self.write8(bus, self.regs.get_hl(), self.regs.l),
+            0x77 => self.write8(bus, self.regs.get_hl(), self.regs.a),
 
             // Halt
-            0x76 => self.halt = true,
+            0x76 => {
+                let int_enable = bus.int_enable();
+                let int_flag = bus.int_flag();
+                if !self.ime && int_enable & int_flag & 0x1F != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halt = true;
+                }
+            }
 
             // LD A,R
             0x78 => self.regs.a = self.regs.b,
@@ -396,13 +515,13 @@ impl CPU {
             0x7F => {},
 
             // LD R,(HL)
-            0x46 => self.regs.b = self.read8(self.regs.get_hl()),
-            0x4E => self.regs.c = self.read8(self.regs.get_hl()),
-            0x56 => self.regs.d = self.read8(self.regs.get_hl()),
-            0x5E => self.regs.e = self.read8(self.regs.get_hl()),
-            0x66 => self.regs.h = self.read8(self.regs.get_hl()),
-            0x6E => self.regs.l = self.read8(self.regs.get_hl()),
-            0x7E => self.regs.a = self.read8(self.regs.get_hl()),
+            0x46 => self.regs.b = self.read8(bus, self.regs.get_hl()),
+            0x4E => self.regs.c = self.read8(bus, self.regs.get_hl()),
+            0x56 => self.regs.d = self.read8(bus, self.regs.get_hl()),
+            0x5E => self.regs.e = self.read8(bus, self.regs.get_hl()),
+            0x66 => self.regs.h = self.read8(bus, self.regs.get_hl()),
+            0x6E => self.regs.l = self.read8(bus, self.regs.get_hl()),
+            0x7E => self.regs.a = self.read8(bus, self.regs.get_hl()),
 
             // ADD
             0x80 => self.regs.a = self.add(self.regs.b),
@@ -413,12 +532,12 @@ impl CPU {
             0x85 => self.regs.a = self.add(self.regs.l),
             0x86 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.add(dat);
             }
             0x87 => self.regs.a = self.add(self.regs.a),
             0xC6 => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.add(dat);
             }
 
@@ -431,12 +550,12 @@ impl CPU {
             0x8D => self.regs.a = self.adc(self.regs.l),
             0x8E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.adc(dat);
             }
             0x8F => self.regs.a = self.adc(self.regs.a),
             0xCE => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.adc(dat);
             }
 
@@ -449,12 +568,12 @@ impl CPU {
             0x95 => self.regs.a = self.sub(self.regs.l),
             0x96 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.sub(dat);
             }
             0x97 => self.regs.a = self.sub(self.regs.a),
             0xD6 => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.sub(dat);
             }
 
@@ -467,12 +586,12 @@ impl CPU {
             0x9D => self.regs.a = self.sbc(self.regs.l),
             0x9E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.sbc(dat);
             }
             0x9F => self.regs.a = self.sbc(self.regs.a),
             0xDE => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.sbc(dat);
             }
 
@@ -485,12 +604,12 @@ impl CPU {
             0xA5 => self.regs.a = self.and(self.regs.l),
             0xA6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.and(dat);
             }
             0xA7 => self.regs.a = self.and(self.regs.a),
             0xE6 => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.and(dat);
             }
 
@@ -503,12 +622,12 @@ impl CPU {
             0xAD => self.regs.a = self.xor(self.regs.l),
             0xAE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.xor(dat);
             }
             0xAF => self.regs.a = self.xor(self.regs.a),
             0xEE => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.xor(dat);
             }
 
@@ -521,12 +640,12 @@ impl CPU {
             0xB5 => self.regs.a = self.or(self.regs.l),
             0xB6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.regs.a = self.or(dat);
             }
             0xB7 => self.regs.a = self.or(self.regs.a),
             0xF6 => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.regs.a = self.or(dat);
             }
 
@@ -539,29 +658,29 @@ impl CPU {
             0xBD => self.cp(self.regs.l),
             0xBE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.cp(dat);
             }
             0xBF => self.cp(self.regs.a),
             0xFE => {
-                let dat = self.imm8();
+                let dat = self.imm8(bus);
                 self.cp(dat);
             }
 
             // RET
-            0xC0 => if !self.regs.get_z() { self.regs.pc = self.pop() },
-            0xC8 => if self.regs.get_z() { self.regs.pc = self.pop() },
-            0xC9 => self.regs.pc = self.pop(),
-            0xD0 => if !self.regs.get_c() { self.regs.pc = self.pop() },
-            0xD8 => if self.regs.get_c() { self.regs.pc = self.pop() },
+            0xC0 => if !self.regs.get_z() { self.regs.pc = self.pop(bus) },
+            0xC8 => if self.regs.get_z() { self.regs.pc = self.pop(bus) },
+            0xC9 => self.regs.pc = self.pop(bus),
+            0xD0 => if !self.regs.get_c() { self.regs.pc = self.pop(bus) },
+            0xD8 => if self.regs.get_c() { self.regs.pc = self.pop(bus) },
             0xD9 => {
-                self.regs.pc = self.pop();
+                self.regs.pc = self.pop(bus);
                 self.ime = true;
             }
 
             // POP
             0xC1 | 0xD1 | 0xE1 | 0xF1 => {
-                let dat = self.pop();
+                let dat = self.pop(bus);
                 match self.opcode {
                     0xC1 => self.regs.set_bc(dat),
                     0xD1 => self.regs.set_de(dat),
@@ -572,106 +691,106 @@ impl CPU {
             }
 
             // JP
-            0xC2 => if !self.regs.get_z() { self.regs.pc = self.imm16() } else { self.regs.pc += 2 },
-            0xC3 => self.regs.pc = self.imm16(),
-            0xCA => if self.regs.get_z() { self.regs.pc = self.imm16() } else { self.regs.pc += 2 },
-            0xD2 => if !self.regs.get_c() { self.regs.pc = self.imm16() } else { self.regs.pc += 2 },
-            0xDA => if self.regs.get_c() { self.regs.pc = self.imm16() } else { self.regs.pc += 2 },
+            0xC2 => if !self.regs.get_z() { self.regs.pc = self.imm16(bus) } else { self.regs.pc += 2 },
+            0xC3 => self.regs.pc = self.imm16(bus),
+            0xCA => if self.regs.get_z() { self.regs.pc = self.imm16(bus) } else { self.regs.pc += 2 },
+            0xD2 => if !self.regs.get_c() { self.regs.pc = self.imm16(bus) } else { self.regs.pc += 2 },
+            0xDA => if self.regs.get_c() { self.regs.pc = self.imm16(bus) } else { self.regs.pc += 2 },
             0xE9 => self.regs.pc = self.regs.get_hl(),  // For real??
 
             // CALL
             0xC4 => if !self.regs.get_z() {
-                let dat = self.imm16();
-                self.push(self.regs.pc);
+                let dat = self.imm16(bus);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = dat;
             } else {
                 self.regs.pc += 2;
             }
             0xCC => if self.regs.get_z() {
-                let dat = self.imm16();
-                self.push(self.regs.pc);
+                let dat = self.imm16(bus);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = dat;
             } else {
                 self.regs.pc += 2;
             }
             0xCD => {
-                let dat = self.imm16();
-                self.push(self.regs.pc);
+                let dat = self.imm16(bus);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = dat;
             }
             0xD4 => if !self.regs.get_c() {
-                let dat = self.imm16();
-                self.push(self.regs.pc);
+                let dat = self.imm16(bus);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = dat;
             } else {
                 self.regs.pc += 2;
             }
             0xDC => if self.regs.get_c() {
-                let dat = self.imm16();
-                self.push(self.regs.pc);
+                let dat = self.imm16(bus);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = dat;
             } else {
                 self.regs.pc += 2;
             }
 
             // PUSH
-            0xC5 => self.push(self.regs.get_bc()),
-            0xD5 => self.push(self.regs.get_de()),
-            0xE5 => self.push(self.regs.get_hl()),
-            0xF5 => self.push(self.regs.get_af()),
+            0xC5 => self.push(bus, self.regs.get_bc()),
+            0xD5 => self.push(bus, self.regs.get_de()),
+            0xE5 => self.push(bus, self.regs.get_hl()),
+            0xF5 => self.push(bus, self.regs.get_af()),
 
             // RST
             0xC7 => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x00;
             }
             0xCF => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x08;
             }
             0xD7 => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x10;
             }
             0xDF => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x18;
             }
             0xE7 => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x20;
             }
             0xEF => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x28;
             }
             0xF7 => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x30;
             }
             0xFF => {
-                self.push(self.regs.pc);
+                self.push(bus, self.regs.pc);
                 self.regs.pc = 0x38;
             }
 
             // LD (A8),A
             0xE0 => {
-                let addr = 0xFF00 | u16::from(self.imm8());
+                let addr = 0xFF00 | u16::from(self.imm8(bus));
                 if self.debug {
                     println!("0xE0 LDA, addr:{:x}", addr);
                 }
-                self.write8(addr, self.regs.a);
+                self.write8(bus, addr, self.regs.a);
             }
 
             // LD (C),A
             0xE2 => {
                 let addr = 0xFF00 | u16::from(self.regs.c);
-                self.write8(addr, self.regs.a);
+                self.write8(bus, addr, self.regs.a);
             }
 
             // ADD SP,r8
             0xE8 => {
-                let dat = i16::from(self.imm8() as i8) as u16;
+                let dat = i16::from(self.imm8(bus) as i8) as u16;
                 let half_carry = (self.regs.sp & 0xF) + (dat & 0xF) > 0xF;
                 let carry = (self.regs.sp & 0xFF) + (dat & 0xFF) > 0xFF;
 
@@ -685,7 +804,7 @@ impl CPU {
 
             // LD HL,SP+r8
             0xF8 => {
-                let dat = i16::from(self.imm8() as i8) as u16;
+                let dat = i16::from(self.imm8(bus) as i8) as u16;
                 let half_carry = (self.regs.sp & 0xF) + (dat & 0xF) > 0xF;
                 let carry = (self.regs.sp & 0xFF) + (dat & 0xFF) > 0xFF;
 
@@ -702,39 +821,42 @@ impl CPU {
 
             // LD (A16),A
             0xEA => {
-                let addr = self.imm16();
-                self.write8(addr, self.regs.a);
+                let addr = self.imm16(bus);
+                self.write8(bus, addr, self.regs.a);
             }
 
             // LD A,(A8)
             0xF0 => {
-                let addr = 0xFF00 | u16::from(self.imm8());
+                let addr = 0xFF00 | u16::from(self.imm8(bus));
                 if self.debug {
                     println!("0xF0 LDA, addr:{:x}", addr);
                 }
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
             }
             
             // LD A,(C)
             0xF2 => {
                 let addr = 0xFF00 | u16::from(self.regs.c);
-                self.regs.a = self.read8(addr);
+                self.regs.a = self.read8(bus, addr);
             }
 
             // DI
-            0xF3 => self.ime = false,
+            0xF3 => {
+                self.ime = false;
+                self.ei_delay = false;
+            }
 
             // LD A,(A16)
             0xFA => {
-                let addr = self.imm16();
-                self.regs.a = self.read8(addr);
+                let addr = self.imm16(bus);
+                self.regs.a = self.read8(bus, addr);
             }
 
             // EI
-            0xFB => self.ime = true,
+            0xFB => self.ei_delay = true,
 
             // PREFIX CB
-            0xCB => self.prefix_cb(),
+            0xCB => self.prefix_cb(bus),
         }
 
         if self.opcode == 0xCB {
@@ -751,6 +873,11 @@ impl CPU {
             }
         }
 
+        if ei_delay && self.ei_delay {
+            self.ei_delay = false;
+            self.ime = true;
+        }
+
         if self.debug {
             println!("cpu cycles: {:x}", cycles)
         }
@@ -765,8 +892,8 @@ impl CPU {
         }
     }
 
-    pub fn prefix_cb(&mut self) {
-        self.cb_opcode = self.read8(self.regs.pc);
+    pub fn prefix_cb<B: Bus>(&mut self, bus: &mut B) {
+        self.cb_opcode = self.read8(bus, self.regs.pc);
         self.regs.pc += 1;
 
         match self.cb_opcode {
@@ -779,9 +906,9 @@ impl CPU {
             0x05 => self.regs.l = self.rlc(self.regs.l),
             0x06 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.rlc(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x07 => self.regs.a = self.rlc(self.regs.a),
 
@@ -794,9 +921,9 @@ impl CPU {
             0x0D => self.regs.l = self.rrc(self.regs.l),
             0x0E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.rrc(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x0F => self.regs.a = self.rrc(self.regs.a),
 
@@ -809,9 +936,9 @@ impl CPU {
             0x15 => self.regs.l = self.rl(self.regs.l),
             0x16 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.rl(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x17 => self.regs.a = self.rl(self.regs.a),
 
@@ -824,9 +951,9 @@ impl CPU {
             0x1D => self.regs.l = self.rr(self.regs.l),
             0x1E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.rr(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x1F => self.regs.a = self.rr(self.regs.a),
 
@@ -839,9 +966,9 @@ impl CPU {
             0x25 => self.regs.l = self.sla(self.regs.l),
             0x26 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.sla(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x27 => self.regs.a = self.sla(self.regs.a),
 
@@ -854,9 +981,9 @@ impl CPU {
             0x2D => self.regs.l = self.sra(self.regs.l),
             0x2E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.sra(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x2F => self.regs.a = self.sra(self.regs.a),
 
@@ -869,9 +996,9 @@ impl CPU {
             0x35 => self.regs.l = self.swap(self.regs.l),
             0x36 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.swap(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x37 => self.regs.a = self.swap(self.regs.a),
 
@@ -884,9 +1011,9 @@ impl CPU {
             0x3D => self.regs.l = self.srl(self.regs.l),
             0x3E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 let ret = self.srl(dat);
-                self.write8(addr, ret);
+                self.write8(bus, addr, ret);
             }
             0x3F => self.regs.a = self.srl(self.regs.a),
 
@@ -899,7 +1026,7 @@ impl CPU {
             0x45 => self.bit(self.regs.l, 0),
             0x46 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 0);
             }
             0x47 => self.bit(self.regs.a, 0),
@@ -913,7 +1040,7 @@ impl CPU {
             0x4D => self.bit(self.regs.l, 1),
             0x4E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 1);
             }
             0x4F => self.bit(self.regs.a, 1),
@@ -927,7 +1054,7 @@ impl CPU {
             0x55 => self.bit(self.regs.l, 2),
             0x56 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 2);
             }
             0x57 => self.bit(self.regs.a, 2),
@@ -941,7 +1068,7 @@ impl CPU {
             0x5D => self.bit(self.regs.l, 3),
             0x5E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 3);
             }
             0x5F => self.bit(self.regs.a, 3),
@@ -955,7 +1082,7 @@ impl CPU {
             0x65 => self.bit(self.regs.l, 4),
             0x66 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 4);
             }
             0x67 => self.bit(self.regs.a, 4),
@@ -969,7 +1096,7 @@ impl CPU {
             0x6D => self.bit(self.regs.l, 5),
             0x6E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 5);
             }
             0x6F => self.bit(self.regs.a, 5),
@@ -983,7 +1110,7 @@ impl CPU {
             0x75 => self.bit(self.regs.l, 6),
             0x76 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 6);
             }
             0x77 => self.bit(self.regs.a, 6),
@@ -997,7 +1124,7 @@ impl CPU {
             0x7D => self.bit(self.regs.l, 7),
             0x7E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
+                let dat = self.read8(bus, addr);
                 self.bit(dat, 7);
             }
             0x7F => self.bit(self.regs.a, 7),
@@ -1011,8 +1138,8 @@ impl CPU {
             0x85 => self.regs.l &= !(0x01),
             0x86 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01));
             }
             0x87 => self.regs.a &= !(0x01),
 
@@ -1025,8 +1152,8 @@ impl CPU {
             0x8D => self.regs.l &= !(0x01 << 1),
             0x8E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 1));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 1));
             }
             0x8F => self.regs.a &= !(0x01 << 1),
 
@@ -1039,8 +1166,8 @@ impl CPU {
             0x95 => self.regs.l &= !(0x01 << 2),
             0x96 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 2));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 2));
             }
             0x97 => self.regs.a &= !(0x01 << 2),
 
@@ -1053,8 +1180,8 @@ impl CPU {
             0x9D => self.regs.l &= !(0x01 << 3),
             0x9E => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 3));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 3));
             }
             0x9F => self.regs.a &= !(0x01 << 3),
 
@@ -1067,8 +1194,8 @@ impl CPU {
             0xA5 => self.regs.l &= !(0x01 << 4),
             0xA6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 4));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 4));
             }
             0xA7 => self.regs.a &= !(0x01 << 4),
 
@@ -1081,8 +1208,8 @@ impl CPU {
             0xAD => self.regs.l &= !(0x01 << 5),
             0xAE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 5));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 5));
             }
             0xAF => self.regs.a &= !(0x01 << 5),
 
@@ -1095,8 +1222,8 @@ impl CPU {
             0xB5 => self.regs.l &= !(0x01 << 6),
             0xB6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 6));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 6));
             }
             0xB7 => self.regs.a &= !(0x01 << 6),
 
@@ -1109,8 +1236,8 @@ impl CPU {
             0xBD => self.regs.l &= !(0x01 << 7),
             0xBE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat & !(0x01 << 7));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat & !(0x01 << 7));
             }
             0xBF => self.regs.a &= !(0x01 << 7),
 
@@ -1123,8 +1250,8 @@ impl CPU {
             0xC5 => self.regs.l |= 0x01,
             0xC6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01));
             }
             0xC7 => self.regs.a |= 0x01,
 
@@ -1137,8 +1264,8 @@ impl CPU {
             0xCD => self.regs.l |= 0x01 << 1,
             0xCE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 1));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 1));
             }
             0xCF => self.regs.a |= 0x01 << 1,
 
@@ -1151,8 +1278,8 @@ impl CPU {
             0xD5 => self.regs.l |= 0x01 << 2,
             0xD6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 2));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 2));
             }
             0xD7 => self.regs.a |= 0x01 << 0,
 
@@ -1165,8 +1292,8 @@ impl CPU {
             0xDD => self.regs.l |= 0x01 << 3,
             0xDE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 3));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 3));
             }
             0xDF => self.regs.a |= 0x01 << 3,
 
@@ -1179,8 +1306,8 @@ impl CPU {
             0xE5 => self.regs.l |= 0x01 << 4,
             0xE6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 4));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 4));
             }
             0xE7 => self.regs.a |= 0x01 << 4,
 
@@ -1193,8 +1320,8 @@ impl CPU {
             0xED => self.regs.l |= 0x01 << 5,
             0xEE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 5));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 5));
             }
             0xEF => self.regs.a |= 0x01 << 5,
 
@@ -1207,8 +1334,8 @@ impl CPU {
             0xF5 => self.regs.l |= 0x01 << 6,
             0xF6 => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 6));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 6));
             }
             0xF7 => self.regs.a |= 0x01 << 6,
 
@@ -1221,45 +1348,60 @@ impl CPU {
             0xFD => self.regs.l |= 0x01 << 7,
             0xFE => {
                 let addr = self.regs.get_hl();
-                let dat = self.read8(addr);
-                self.write8(addr, dat | (0x01 << 7));
+                let dat = self.read8(bus, addr);
+                self.write8(bus, addr, dat | (0x01 << 7));
             }
             0xFF => self.regs.a |= 0x01 << 7,
         }
     }
 
-    pub fn read8(&mut self, addr: u16) -> u8 {
-        self.mmc.borrow_mut().read(addr)
+    // Advances the bus by one M-cycle.
+    fn tick<B: Bus>(&mut self, bus: &mut B) {
+        self.ticked += 1;
+        if self.stop {
+            bus.tick_stopped(4);
+        } else {
+            bus.tick(4);
+        }
+    }
+
+    // Every access takes one M-cycle, so DMA, the PPU and the timer advance between
+    // the accesses of an instruction.
+    pub fn read8<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        let dat = bus.read(addr);
+        self.tick(bus);
+        dat
     }
 
-    pub fn read16(&mut self, addr: u16) -> u16 {
-        //println!("read8 addr:{:x}, dat:{:x}", addr, self.read8(addr));
-        //println!("read8 addr:{:x}, dat:{:x}", addr+1, self.read8(addr+1));
-        u16::from(self.read8(addr)) | u16::from(self.read8(addr.wrapping_add(1))) << 8
+    pub fn read16<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u16 {
+        //println!("read8 addr:{:x}, dat:{:x}", addr, self.read8(bus, addr));
+        //println!("read8 addr:{:x}, dat:{:x}", addr+1, self.read8(bus, addr+1));
+        u16::from(self.read8(bus, addr)) | u16::from(self.read8(bus, addr.wrapping_add(1))) << 8
     }
 
-    pub fn imm8(&mut self) -> u8 {
-        let ret = self.read8(self.regs.pc);
+    pub fn imm8<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let ret = self.read8(bus, self.regs.pc);
         self.regs.pc += 1;
         ret
     }
 
-    pub fn imm16(&mut self) -> u16 {
-        let ret = self.read16(self.regs.pc);
+    pub fn imm16<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let ret = self.read16(bus, self.regs.pc);
         // println!("imm16 pc:{:x} ret:{:x}", self.regs.pc, ret);
         self.regs.pc += 2;
         ret
     }
 
-    pub fn write8(&mut self, addr: u16, dat: u8) {
-        self.mmc.borrow_mut().write(addr, dat);
+    pub fn write8<B: Bus>(&mut self, bus: &mut B, addr: u16, dat: u8) {
+        bus.write(addr, dat);
+        self.tick(bus);
     }
 
-    pub fn write16(&mut self, addr: u16, dat: u16) {
+    pub fn write16<B: Bus>(&mut self, bus: &mut B, addr: u16, dat: u16) {
         //println!("write8 addr:{:x}, dat:{:x}", addr, dat&0xff);
         //println!("write8 addr:{:x}, dat:{:x}", addr+1, dat>>8);
-        self.write8(addr.wrapping_add(1), (dat >> 8) as u8);
-        self.write8(addr, (dat & 0xFF) as u8);
+        self.write8(bus, addr.wrapping_add(1), (dat >> 8) as u8);
+        self.write8(bus, addr, (dat & 0xFF) as u8);
     }
 
     pub fn inc(&mut self, r: u8) -> u8 {
@@ -1393,15 +1535,15 @@ impl CPU {
         self.regs.set_c(carry);
     }
 
-    pub fn pop(&mut self) -> u16 {
-        let ret = self.read16(self.regs.sp);
+    pub fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let ret = self.read16(bus, self.regs.sp);
         self.regs.sp = self.regs.sp.wrapping_add(2);
         ret
     }
 
-    pub fn push(&mut self, dat: u16) {
+    pub fn push<B: Bus>(&mut self, bus: &mut B, dat: u16) {
         self.regs.sp = self.regs.sp.wrapping_sub(2);
-        self.write16(self.regs.sp, dat);
+        self.write16(bus, self.regs.sp, dat);
     }
 
     pub fn jr(&mut self, offset: u8) {
@@ -1485,7 +1627,7 @@ impl CPU {
     }
 
     pub fn swap(&mut self, r: u8) -> u8 {
-        let ret = (r << 4) | (r >> 4);
+        let ret = r.rotate_right(4);
 
         self.regs.set_z(ret == 0);
         self.regs.set_n(false);
@@ -1514,13 +1656,13 @@ impl CPU {
         self.regs.set_h(true);
     }
 
-    pub fn handle_interrupt(&mut self) -> u32 {
+    pub fn handle_interrupt<B: Bus>(&mut self, bus: &mut B) -> u32 {
         if !self.ime && !self.halt {
             return 0;
         }
 
-        let int_enable: u8 = self.read8(IoRegs::IE as u16);
-        let mut int_flag: u8 = self.read8(IoRegs::IF as u16);
+        let int_enable: u8 = bus.int_enable();
+        let int_flag: u8 = bus.int_flag();
 
         /*
         if self.debug {
@@ -1529,43 +1671,68 @@ impl CPU {
         }
         */
 
-        let fired_interrupt: u8 = int_enable & int_flag;
+        let fired_interrupt: u8 = int_enable & int_flag & 0x1F;
         if fired_interrupt == 0 {
             return 0;
         }
-        self.halt = false;
+
+        // Leaving HALT takes one extra M-cycle.
+        let mut cycles = 0;
+        if self.halt {
+            self.halt = false;
+            self.tick(bus);
+            cycles += 1;
+        }
 
         if !self.ime {
-            return 0;
+            return cycles;
         }
         self.ime = false;
 
-        self.push(self.regs.pc);
-        if fired_interrupt > 0 {
-            if fired_interrupt & (IntFlag::VBLANK as u8) > 0 {
-                self.regs.pc = 0x40;
-                int_flag &= !(IntFlag::VBLANK as u8);
-            } else if fired_interrupt & (IntFlag::STAT as u8) > 0 {
-                self.regs.pc = 0x48;
-                int_flag &= !(IntFlag::STAT as u8);
-            } else if fired_interrupt & (IntFlag::TIMER as u8) > 0 {
-                self.regs.pc = 0x50;
-                int_flag &= !(IntFlag::TIMER as u8);
-            } else if fired_interrupt & (IntFlag::SERIAL as u8) > 0 {
-                self.regs.pc = 0x58;
-                int_flag &= !(IntFlag::SERIAL as u8);
-            } else if fired_interrupt & (IntFlag::JOYPAD as u8) > 0 {
-                self.regs.pc = 0x60;
-                int_flag &= !(IntFlag::JOYPAD as u8);
-            }
-        }
-        self.write8(IoRegs::IF as u16, int_flag);
+        // M1-M2: internal delay.
+        self.tick(bus);
+        self.tick(bus);
+
+        // M3: push the high byte of PC.
+        let pc = self.regs.pc;
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write8(bus, self.regs.sp, (pc >> 8) as u8);
+
+        // The vector is chosen after the high byte is pushed,
+        // so a push overwriting IE (0xFFFF) can cancel or redirect the interrupt.
+        let int_enable: u8 = bus.int_enable();
+        let int_flag: u8 = bus.int_flag();
+        let fired_interrupt: u8 = int_enable & int_flag & 0x1F;
+        let (vector, flag) = if fired_interrupt & (IntFlag::VBLANK as u8) > 0 {
+            (0x40, IntFlag::VBLANK as u8)
+        } else if fired_interrupt & (IntFlag::STAT as u8) > 0 {
+            (0x48, IntFlag::STAT as u8)
+        } else if fired_interrupt & (IntFlag::TIMER as u8) > 0 {
+            (0x50, IntFlag::TIMER as u8)
+        } else if fired_interrupt & (IntFlag::SERIAL as u8) > 0 {
+            (0x58, IntFlag::SERIAL as u8)
+        } else if fired_interrupt & (IntFlag::JOYPAD as u8) > 0 {
+            (0x60, IntFlag::JOYPAD as u8)
+        } else {
+            // A cancelled dispatch jumps to 0x0000.
+            (0x00, 0)
+        };
+        bus.set_int_flag(int_flag & !flag);
+
+        // M4: push the low byte of PC.
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write8(bus, self.regs.sp, (pc & 0xFF) as u8);
+
+        // M5: jump to the vector.
+        self.regs.pc = vector;
+        self.interrupt = Some(vector);
+        self.tick(bus);
 
-        4
+        cycles + 5
     }
 
-    pub fn debug_out(&mut self) {
-        let opcode = self.read8(self.regs.pc);
+    pub fn debug_out<B: Bus>(&mut self, bus: &mut B) {
+        let opcode = bus.read(self.regs.pc);
 
         let inst_name: [&str; 0x100] = [
             "NOP", "LD BC,nn", "LD (BC),A", "INC BC", "INC B", "DEC B", "LD B,n", "RLCA",
@@ -1640,7 +1807,7 @@ impl CPU {
         let mut str = inst_name[opcode as usize];
         let mut str_opcode: u16 = opcode as u16;
         if opcode == 0xCB {
-            let cb_opcode = self.read8(self.regs.pc + 1);
+            let cb_opcode = bus.read(self.regs.pc + 1);
             str = cb_inst_name[cb_opcode as usize];
             str_opcode = u16::from(opcode) << 8 | u16::from(cb_opcode);
         }
@@ -1649,3 +1816,9 @@ impl CPU {
                 self.regs.pc, self.regs.sp, self.regs.a, self.regs.f, self.regs.b, self.regs.c, self.regs.d, self.regs.e, self.regs.h, self.regs.l, str_opcode, str);
     }
 }
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
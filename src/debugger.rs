@@ -0,0 +1,530 @@
+use std::{convert::TryFrom, io::{self, BufRead, Write}};
+
+use crate::cpu::CPU;
+use crate::defs::CLOCKS_PER_FRAME;
+use crate::disasm;
+use crate::gameboy::Gameboy;
+use crate::mmc::MMC;
+
+// Commands that run freely give up after this many frames.
+const RUN_LIMIT_FRAMES: u32 = 600;
+
+const INTERRUPTS: [&str; 5] = ["vblank", "stat", "timer", "serial", "joypad"];
+
+const HELP: &str = "\
+Addresses and values are hex (0x or $ prefix optional), counts are decimal.
+  c, continue              resume emulation
+  s, step [n]              execute n instructions (default 1)
+  n, next                  step over CALL and RST
+  finish                   run until the current function returns
+  line <ly>                run until the PPU reaches scanline ly
+  frame [n]                run n frames (default 1)
+  b, break                 list breakpoints
+  b, break [bank:]addr     break before executing addr, optionally only in a ROM/RAM bank
+  b, break int [name]      break on interrupt entry: vblank, stat, timer, serial, joypad (default all)
+  d, delete [n | int]      delete breakpoint n, the interrupt breakpoints, or everything
+  watch                    list watchpoints
+  watch <r|w> <range>      stop on a read or write of [bank:]addr[-end]
+  watch v <range> <byte>   stop on a write of byte to the range
+  unwatch [n]              delete watchpoint n or all of them
+  r, regs                  print registers
+  set <reg> <value>        set a, f, b, c, d, e, h, l, af, bc, de, hl, sp, pc or ime
+  x <addr> [len]           examine memory (default 64 bytes)
+  w, write <addr> <byte>.. write memory
+  l, disasm [addr] [n]     disassemble n instructions (default 10), around PC without addr
+  trace <cpu|ppu|timer> <on|off>
+  q, quit                  exit the emulator
+An empty line repeats the last command.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    // Only break while this bank is mapped at `addr`.
+    pub bank: Option<u16>,
+}
+
+// Checked by `Gameboy::run_frame` after every instruction unless empty.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    pub addrs: Vec<Breakpoint>,
+    // Interrupts to break on at entry, as IF bits.
+    pub interrupts: u8,
+}
+
+impl Breakpoints {
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty() && self.interrupts == 0
+    }
+
+    // Why execution should stop before the next instruction, if it should.
+    pub fn check(&self, cpu: &CPU, mmc: &MMC) -> Option<String> {
+        if let Some(i) = cpu.interrupt.and_then(interrupt_index) {
+            if self.interrupts & (1 << i) != 0 {
+                return Some(format!("{} interrupt", INTERRUPTS[i]));
+            }
+        }
+
+        // A halted CPU didn't execute anything.
+        if cpu.halt {
+            return None;
+        }
+        let pc = cpu.regs.pc;
+        self.addrs.iter()
+            .position(|bp| bp.addr == pc && bp.bank.is_none_or(|bank| bank == mmc.bank(pc)))
+            .map(|i| format!("breakpoint {} at {}", i + 1, format_addr(mmc, pc)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // A write of this value.
+    Value(u8),
+}
+
+// Checked by the MMC on every CPU access unless there are none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    // Inclusive range.
+    pub start: u16,
+    pub end: u16,
+    // Only trigger while this bank is mapped at the address.
+    pub bank: Option<u16>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: Access, addr: u16, bank: u16, new: u8) -> bool {
+        let kind = match (self.kind, access) {
+            (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write) => true,
+            (WatchKind::Value(value), Access::Write) => value == new,
+            _ => false,
+        };
+        kind && (self.start..=self.end).contains(&addr) && self.bank.is_none_or(|b| b == bank)
+    }
+}
+
+// The first watchpoint hit since the last instruction. A read has `old == new`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub index: usize,
+    pub access: Access,
+    pub addr: u16,
+    pub bank: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl WatchHit {
+    // `pc` is the instruction that made the access.
+    pub fn describe(&self, pc: u16, cycle: u64) -> String {
+        let addr = banked_addr(self.addr, self.bank);
+        let access = match self.access {
+            Access::Read => format!("read {} = {:02x}", addr, self.new),
+            Access::Write => format!("write {} {:02x} -> {:02x}", addr, self.old, self.new),
+        };
+        format!("watchpoint {}: {} by pc {:04x} at cycle {}", self.index + 1, access, pc, cycle)
+    }
+}
+
+fn interrupt_index(vector: u16) -> Option<usize> {
+    match vector {
+        0x40 | 0x48 | 0x50 | 0x58 | 0x60 => Some(((vector - 0x40) / 8) as usize),
+        _ => None,
+    }
+}
+
+fn format_addr(mmc: &MMC, addr: u16) -> String {
+    banked_addr(addr, mmc.bank(addr))
+}
+
+// `bank:addr` in banked areas, `addr` elsewhere.
+fn banked_addr(addr: u16, bank: u16) -> String {
+    match addr {
+        0x4000..=0x7FFF | 0xA000..=0xBFFF | 0xD000..=0xDFFF => format!("{:02x}:{:04x}", bank, addr),
+        _ => format!("{:04x}", addr),
+    }
+}
+
+pub enum DebugAction {
+    Continue,
+    Quit,
+}
+
+enum Stop {
+    Done,
+    Break(String),
+    Limit,
+}
+
+pub struct Debugger {
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            last_command: String::new(),
+        }
+    }
+
+    // Reads commands from stdin until `continue` or `quit`. `reason` is why emulation stopped.
+    pub fn repl(&mut self, gameboy: &mut Gameboy, reason: Option<&str>) -> DebugAction {
+        if let Some(reason) = reason {
+            println!("stopped: {}", reason);
+        }
+        print_position(gameboy);
+
+        let stdin = io::stdin();
+        loop {
+            print!("(deepboy) ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return DebugAction::Continue,
+                Ok(_) => {},
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match self.execute(gameboy, &words) {
+                Ok(Some(action)) => return action,
+                Ok(None) => {},
+                Err(msg) => println!("{}", msg),
+            }
+        }
+    }
+
+    // Runs one command. Returns an action when the debugger should be left.
+    pub fn execute(&mut self, gameboy: &mut Gameboy, words: &[&str]) -> Result<Option<DebugAction>, String> {
+        match words {
+            [] => {},
+            ["c" | "continue"] => return Ok(Some(DebugAction::Continue)),
+            ["q" | "quit"] => return Ok(Some(DebugAction::Quit)),
+            ["h" | "help"] => println!("{}", HELP),
+            ["s" | "step"] => run(gameboy, |_, _| true),
+            ["s" | "step", n] => {
+                let mut left = parse_count(n)?;
+                run(gameboy, |_, _| { left -= 1; left == 0 });
+            }
+            ["n" | "next"] => {
+                let pc = gameboy.cpu.cpu.regs.pc;
+                let sp = gameboy.cpu.cpu.regs.sp;
+                let (_, len) = disasm::disassemble(|addr| gameboy.read_memory(addr), pc);
+                let ret = pc.wrapping_add(len);
+                if is_call(gameboy.read_memory(pc)) {
+                    run(gameboy, |gb, _| gb.cpu.cpu.regs.pc == ret && gb.cpu.cpu.regs.sp == sp);
+                } else {
+                    run(gameboy, |_, _| true);
+                }
+            }
+            ["finish"] => {
+                let sp = gameboy.cpu.cpu.regs.sp;
+                run(gameboy, |gb, _| is_return(gb.cpu.cpu.opcode) && gb.cpu.cpu.regs.sp > sp);
+            }
+            ["line", ly] => {
+                let ly = match ly.parse::<u32>() {
+                    Ok(ly) if ly <= 153 => ly,
+                    _ => return Err(format!("invalid scanline `{}` (0-153)", ly)),
+                };
+                // Leave the target line first when already on it.
+                let mut left = u32::from(gameboy.read_memory(0xFF44)) != ly;
+                run(gameboy, |gb, _| {
+                    let on_line = u32::from(gb.read_memory(0xFF44)) == ly;
+                    let done = left && on_line;
+                    left |= !on_line;
+                    done
+                });
+            }
+            ["frame"] => run(gameboy, |_, completed| completed),
+            ["frame", n] => {
+                let mut left = parse_count(n)?;
+                run(gameboy, |_, completed| {
+                    if completed {
+                        left -= 1;
+                    }
+                    left == 0
+                });
+            }
+            ["b" | "break"] => print_breakpoints(gameboy),
+            ["b" | "break", "int"] => gameboy.breakpoints.interrupts = 0x1F,
+            ["b" | "break", "int", name] => {
+                let i = INTERRUPTS.iter().position(|n| n == name)
+                    .ok_or_else(|| format!("unknown interrupt `{}`", name))?;
+                gameboy.breakpoints.interrupts |= 1 << i;
+            }
+            ["b" | "break", spec] => {
+                let (bank, addr) = match spec.split_once(':') {
+                    Some((bank, addr)) => (Some(parse_hex(bank)?), parse_hex(addr)?),
+                    None => (None, parse_hex(spec)?),
+                };
+                gameboy.breakpoints.addrs.push(Breakpoint { addr, bank });
+                println!("breakpoint {} at {}", gameboy.breakpoints.addrs.len(), spec);
+            }
+            ["d" | "delete"] => gameboy.breakpoints = Default::default(),
+            ["d" | "delete", "int"] => gameboy.breakpoints.interrupts = 0,
+            ["d" | "delete", n] => {
+                let n = parse_count(n)? as usize;
+                if n == 0 || n > gameboy.breakpoints.addrs.len() {
+                    return Err(format!("no breakpoint {}", n));
+                }
+                gameboy.breakpoints.addrs.remove(n - 1);
+            }
+            ["watch"] => print_watchpoints(gameboy),
+            ["watch", kind, range, value @ ..] => {
+                let kind = match (*kind, value) {
+                    ("r" | "read", []) => WatchKind::Read,
+                    ("w" | "write", []) => WatchKind::Write,
+                    ("v" | "value", [value]) => match parse_hex(value)? {
+                        value @ 0..=0xFF => WatchKind::Value(value as u8),
+                        _ => return Err(format!("value out of range `{}`", value)),
+                    },
+                    _ => return Err("expected `watch r|w <range>` or `watch v <range> <byte>`".to_string()),
+                };
+                let (bank, range) = match range.split_once(':') {
+                    Some((bank, range)) => (Some(parse_hex(bank)?), range),
+                    None => (None, *range),
+                };
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+                    None => (parse_hex(range)?, parse_hex(range)?),
+                };
+                if end < start {
+                    return Err(format!("empty range `{}`", range));
+                }
+                gameboy.mmc.watchpoints.push(Watchpoint { start, end, bank, kind });
+                println!("watchpoint {}", gameboy.mmc.watchpoints.len());
+            }
+            ["unwatch"] => gameboy.mmc.watchpoints.clear(),
+            ["unwatch", n] => {
+                let n = parse_count(n)? as usize;
+                if n > gameboy.mmc.watchpoints.len() {
+                    return Err(format!("no watchpoint {}", n));
+                }
+                gameboy.mmc.watchpoints.remove(n - 1);
+            }
+            ["r" | "regs"] => print_regs(gameboy),
+            ["set", reg, value] => set_register(gameboy, reg, parse_hex(value)?)?,
+            ["x", addr] => examine(gameboy, parse_hex(addr)?, 64),
+            ["x", addr, len] => examine(gameboy, parse_hex(addr)?, parse_count(len)?),
+            ["w" | "write", addr, bytes @ ..] if !bytes.is_empty() => {
+                let addr = parse_hex(addr)?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    let byte = parse_hex(byte)?;
+                    if byte > 0xFF {
+                        return Err(format!("value out of range `{}`", byte));
+                    }
+                    gameboy.mmc.write(addr.wrapping_add(i as u16), byte as u8);
+                }
+            }
+            ["l" | "disasm"] => {
+                let pc = gameboy.cpu.cpu.regs.pc;
+                let (start, before) = start_before(gameboy, pc);
+                disassemble(gameboy, start, before + 6);
+            }
+            ["l" | "disasm", addr] => disassemble(gameboy, parse_hex(addr)?, 10),
+            ["l" | "disasm", addr, n] => disassemble(gameboy, parse_hex(addr)?, parse_count(n)?),
+            ["trace", component, state] => {
+                let on = match *state {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("expected `on` or `off`, found `{}`", state)),
+                };
+                match *component {
+                    "cpu" => gameboy.cpu.set_debug(on),
+                    "ppu" => gameboy.mmc.ppu.set_debug(on),
+                    "timer" => gameboy.mmc.timer.set_debug(on),
+                    _ => return Err(format!("unknown component `{}`", component)),
+                }
+            }
+            _ => return Err(format!("unknown command `{}`, try `help`", words.join(" "))),
+        }
+        Ok(None)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Executes instructions until a breakpoint or watchpoint is hit, `done` returns true after one
+// or the limit is reached, then prints where it stopped. `done` also gets whether the instruction completed a frame.
+fn run<F: FnMut(&Gameboy, bool) -> bool>(gameboy: &mut Gameboy, mut done: F) {
+    let limit = CLOCKS_PER_FRAME * RUN_LIMIT_FRAMES;
+    let mut cycles = 0;
+    let stop = loop {
+        let pc = gameboy.cpu.cpu.regs.pc;
+        cycles += gameboy.step_in_frame();
+        let completed = gameboy.poll_frame();
+        if let Some(reason) = gameboy.check_break(pc) {
+            break Stop::Break(reason);
+        }
+        if done(gameboy, completed) {
+            break Stop::Done;
+        }
+        if cycles >= limit {
+            break Stop::Limit;
+        }
+    };
+
+    match stop {
+        Stop::Done => {},
+        Stop::Break(reason) => println!("stopped: {}", reason),
+        Stop::Limit => println!("stopped: gave up after {} frames", RUN_LIMIT_FRAMES),
+    }
+    print_position(gameboy);
+}
+
+// CALL, CALL cc and RST.
+fn is_call(opcode: u8) -> bool {
+    opcode == 0xCD || opcode & 0xE7 == 0xC4 || opcode & 0xC7 == 0xC7
+}
+
+// RET, RET cc and RETI.
+fn is_return(opcode: u8) -> bool {
+    opcode == 0xC9 || opcode == 0xD9 || opcode & 0xE7 == 0xC0
+}
+
+fn print_position(gameboy: &Gameboy) {
+    let pc = gameboy.cpu.cpu.regs.pc;
+    let (text, _) = disasm::disassemble(|addr| gameboy.read_memory(addr), pc);
+    println!("{}  {}", format_addr(&gameboy.mmc, pc), text);
+}
+
+fn print_regs(gameboy: &Gameboy) {
+    let cpu = &gameboy.cpu.cpu;
+    let regs = &cpu.regs;
+    let flags: String = [(regs.get_z(), 'Z'), (regs.get_n(), 'N'), (regs.get_h(), 'H'), (regs.get_c(), 'C')]
+        .iter().map(|&(set, c)| if set { c } else { '-' }).collect();
+    println!("A:{:02x} F:{:02x} [{}] B:{:02x} C:{:02x} D:{:02x} E:{:02x} H:{:02x} L:{:02x} SP:{:04x} PC:{:04x}",
+             regs.a, regs.f, flags, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l, regs.sp, regs.pc);
+    println!("IME:{} IE:{:02x} IF:{:02x} HALT:{} LY:{} cycles:{}",
+             cpu.ime as u8, gameboy.read_memory(0xFFFF), gameboy.read_memory(0xFF0F),
+             cpu.halt as u8, gameboy.read_memory(0xFF44), gameboy.elapsed_cycles);
+}
+
+fn print_breakpoints(gameboy: &Gameboy) {
+    let breakpoints = &gameboy.breakpoints;
+    if breakpoints.is_empty() {
+        println!("no breakpoints");
+    }
+    for (i, bp) in breakpoints.addrs.iter().enumerate() {
+        match bp.bank {
+            Some(bank) => println!("{}: {:02x}:{:04x}", i + 1, bank, bp.addr),
+            None => println!("{}: {:04x}", i + 1, bp.addr),
+        }
+    }
+    let interrupts: Vec<&str> = INTERRUPTS.iter().enumerate()
+        .filter(|(i, _)| breakpoints.interrupts & (1 << i) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if !interrupts.is_empty() {
+        println!("interrupts: {}", interrupts.join(" "));
+    }
+}
+
+fn print_watchpoints(gameboy: &Gameboy) {
+    let watchpoints = &gameboy.mmc.watchpoints;
+    if watchpoints.is_empty() {
+        println!("no watchpoints");
+    }
+    for (i, w) in watchpoints.iter().enumerate() {
+        let bank = w.bank.map(|bank| format!("{:02x}:", bank)).unwrap_or_default();
+        let range = if w.start == w.end { format!("{:04x}", w.start) } else { format!("{:04x}-{:04x}", w.start, w.end) };
+        match w.kind {
+            WatchKind::Read => println!("{}: read {}{}", i + 1, bank, range),
+            WatchKind::Write => println!("{}: write {}{}", i + 1, bank, range),
+            WatchKind::Value(value) => println!("{}: write of {:02x} to {}{}", i + 1, value, bank, range),
+        }
+    }
+}
+
+fn set_register(gameboy: &mut Gameboy, reg: &str, value: u16) -> Result<(), String> {
+    let cpu = &mut gameboy.cpu.cpu;
+    let byte = || u8::try_from(value).map_err(|_| format!("value out of range `{:x}`", value));
+    let regs = &mut cpu.regs;
+    match reg {
+        "a" => regs.a = byte()?,
+        "f" => regs.f = byte()? & 0xF0,
+        "b" => regs.b = byte()?,
+        "c" => regs.c = byte()?,
+        "d" => regs.d = byte()?,
+        "e" => regs.e = byte()?,
+        "h" => regs.h = byte()?,
+        "l" => regs.l = byte()?,
+        "af" => regs.set_af(value & 0xFFF0),
+        "bc" => regs.set_bc(value),
+        "de" => regs.set_de(value),
+        "hl" => regs.set_hl(value),
+        "sp" => regs.sp = value,
+        "pc" => regs.pc = value,
+        "ime" => cpu.ime = value != 0,
+        _ => return Err(format!("unknown register `{}`", reg)),
+    }
+    Ok(())
+}
+
+fn examine(gameboy: &Gameboy, addr: u16, len: u32) {
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row as u16);
+        let bytes: Vec<u8> = (0..16.min(len - row)).map(|i| gameboy.read_memory(start.wrapping_add(i as u16))).collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = bytes.iter().map(|&b| if b.is_ascii_graphic() { b as char } else { '.' }).collect();
+        println!("{}  {:<47}  {}", format_addr(&gameboy.mmc, start), hex.join(" "), text);
+    }
+}
+
+// The furthest start address up to 4 instructions before `pc` that decodes back into `pc`,
+// and the number of instructions in between.
+fn start_before(gameboy: &Gameboy, pc: u16) -> (u16, u32) {
+    for back in (1..=pc.min(12)).rev() {
+        let start = pc - back;
+        let mut addr = start;
+        let mut count = 0;
+        while addr < pc && count < 4 {
+            addr = addr.wrapping_add(disasm::disassemble(|a| gameboy.read_memory(a), addr).1);
+            count += 1;
+        }
+        if addr == pc {
+            return (start, count);
+        }
+    }
+    (pc, 0)
+}
+
+fn disassemble(gameboy: &Gameboy, mut addr: u16, count: u32) {
+    let pc = gameboy.cpu.cpu.regs.pc;
+    for _ in 0..count {
+        let (text, len) = disasm::disassemble(|a| gameboy.read_memory(a), addr);
+        let bytes: Vec<String> = (0..len).map(|i| format!("{:02x}", gameboy.read_memory(addr.wrapping_add(i)))).collect();
+        let marker = if addr == pc { "=>" } else { "  " };
+        println!("{} {}  {:<8}  {}", marker, format_addr(&gameboy.mmc, addr), bytes.join(" "), text);
+        addr = addr.wrapping_add(len);
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')).unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number `{}`", s))
+}
+
+fn parse_count(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid number `{}`", s)),
+    }
+}
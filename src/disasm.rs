@@ -0,0 +1,85 @@
+// SM83 disassembler. Opcodes are decoded from their bit fields:
+// x = bits 7-6, y = bits 5-3, z = bits 2-0, p = y >> 1, q = y & 1.
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+// Disassembles the instruction at `addr` and returns its text and length in bytes.
+pub fn disassemble<F: Fn(u16) -> u8>(read: F, addr: u16) -> (String, u16) {
+    let op = read(addr);
+    let n = || read(addr.wrapping_add(1));
+    let nn = || u16::from_le_bytes([read(addr.wrapping_add(1)), read(addr.wrapping_add(2))]);
+    // Target of a relative jump.
+    let rel = || addr.wrapping_add(2).wrapping_add(n() as i8 as u16);
+
+    let (x, y, z) = (op >> 6, ((op >> 3) & 7) as usize, (op & 7) as usize);
+    let (p, q) = (y >> 1, y & 1);
+
+    let (text, len) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP".to_string(), 1),
+            1 => (format!("LD (0x{:04x}),SP", nn()), 3),
+            2 => ("STOP".to_string(), 2),
+            3 => (format!("JR 0x{:04x}", rel()), 2),
+            _ => (format!("JR {},0x{:04x}", CC[y - 4], rel()), 2),
+        },
+        (0, 1) if q == 0 => (format!("LD {},0x{:04x}", RP[p], nn()), 3),
+        (0, 1) => (format!("ADD HL,{}", RP[p]), 1),
+        (0, 2) => {
+            let mem = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 { (format!("LD {},A", mem), 1) } else { (format!("LD A,{}", mem), 1) }
+        }
+        (0, 3) => (format!("{} {}", if q == 0 { "INC" } else { "DEC" }, RP[p]), 1),
+        (0, 4) => (format!("INC {}", R[y]), 1),
+        (0, 5) => (format!("DEC {}", R[y]), 1),
+        (0, 6) => (format!("LD {},0x{:02x}", R[y], n()), 2),
+        (0, _) => (["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"][y].to_string(), 1),
+        (1, 6) if y == 6 => ("HALT".to_string(), 1),
+        (1, _) => (format!("LD {},{}", R[y], R[z]), 1),
+        (2, _) => (format!("{}{}", ALU[y], R[z]), 1),
+        (3, 0) => match y {
+            0..=3 => (format!("RET {}", CC[y]), 1),
+            4 => (format!("LDH (0xff{:02x}),A", n()), 2),
+            5 => (format!("ADD SP,{}", n() as i8), 2),
+            6 => (format!("LDH A,(0xff{:02x})", n()), 2),
+            _ => (format!("LD HL,SP{:+}", n() as i8), 2),
+        },
+        (3, 1) if q == 0 => (format!("POP {}", RP2[p]), 1),
+        (3, 1) => (["RET", "RETI", "JP HL", "LD SP,HL"][p].to_string(), 1),
+        (3, 2) => match y {
+            0..=3 => (format!("JP {},0x{:04x}", CC[y], nn()), 3),
+            4 => ("LD (0xff00+C),A".to_string(), 1),
+            5 => (format!("LD (0x{:04x}),A", nn()), 3),
+            6 => ("LD A,(0xff00+C)".to_string(), 1),
+            _ => (format!("LD A,(0x{:04x})", nn()), 3),
+        },
+        (3, 3) => match y {
+            0 => (format!("JP 0x{:04x}", nn()), 3),
+            1 => (disassemble_cb(n()), 2),
+            6 => ("DI".to_string(), 1),
+            7 => ("EI".to_string(), 1),
+            _ => (format!("DB 0x{:02x}", op), 1),
+        },
+        (3, 4) if y < 4 => (format!("CALL {},0x{:04x}", CC[y], nn()), 3),
+        (3, 5) if q == 0 => (format!("PUSH {}", RP2[p]), 1),
+        (3, 5) if p == 0 => (format!("CALL 0x{:04x}", nn()), 3),
+        (3, 6) => (format!("{}0x{:02x}", ALU[y], n()), 2),
+        (3, 7) => (format!("RST 0x{:02x}", y * 8), 1),
+        _ => (format!("DB 0x{:02x}", op), 1),
+    };
+    (text, len)
+}
+
+fn disassemble_cb(op: u8) -> String {
+    let (x, y, z) = (op >> 6, (op >> 3) & 7, (op & 7) as usize);
+    match x {
+        0 => format!("{} {}", ROT[y as usize], R[z]),
+        1 => format!("BIT {},{}", y, R[z]),
+        2 => format!("RES {},{}", y, R[z]),
+        _ => format!("SET {},{}", y, R[z]),
+    }
+}
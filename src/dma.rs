@@ -0,0 +1,111 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const OAM_SIZE: u16 = 0xA0;
+
+// OAM DMA state. The copy itself is done by MMC one byte per M-cycle.
+#[derive(Clone)]
+pub struct Dma {
+    reg: u8,
+    source: u16,
+    index: u16,
+    delay: u32,
+    active: bool,
+    last_byte: u8,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            reg: 0xFF,
+            source: 0,
+            index: 0,
+            delay: 0,
+            active: false,
+            last_byte: 0xFF,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.reg
+    }
+
+    // Writing 0xFF46 (re)starts a transfer after one M-cycle of setup.
+    // A restart keeps the bus locked since the previous transfer is still running.
+    pub fn start(&mut self, dat: u8) {
+        self.reg = dat;
+        self.source = u16::from(dat) << 8;
+        self.index = 0;
+        self.delay = 1;
+    }
+
+    // Sets the register without starting a transfer, e.g. when importing a state.
+    pub fn set_register(&mut self, dat: u8) {
+        *self = Dma::new();
+        self.reg = dat;
+    }
+
+    // The CPU bus is taken while a transfer is running.
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    // The byte currently on the bus. CPU reads outside HRAM see this value mid-transfer.
+    pub fn last_byte(&self) -> u8 {
+        self.last_byte
+    }
+
+    // Advances one M-cycle and returns the (source, destination) pair to copy, if any.
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        if self.delay > 0 {
+            self.delay -= 1;
+            if self.delay == 0 {
+                self.active = true;
+            }
+            return None;
+        }
+
+        if !self.active {
+            return None;
+        }
+
+        let src = self.source + self.index;
+        let dst = 0xFE00 + self.index;
+        self.index += 1;
+        if self.index == OAM_SIZE {
+            self.active = false;
+        }
+        Some((src, dst))
+    }
+
+    pub fn set_last_byte(&mut self, dat: u8) {
+        self.last_byte = dat;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.reg);
+        w.write_u16(self.source);
+        w.write_u16(self.index);
+        w.write_u32(self.delay);
+        w.write_bool(self.active);
+        w.write_u8(self.last_byte);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.reg = r.read_u8()?;
+        self.source = r.read_u16()?;
+        self.index = r.read_u16()?;
+        if self.index > OAM_SIZE {
+            return Err(StateError::Format("invalid DMA index"));
+        }
+        self.delay = r.read_u32()?;
+        self.active = r.read_bool()?;
+        self.last_byte = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
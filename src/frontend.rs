@@ -0,0 +1,165 @@
+use crate::config::{Config, Hotkey};
+use crate::debugger::{DebugAction, Debugger};
+use crate::gameboy::Gameboy;
+use crate::limiter::{self, AudioQueue, Pacing, Speed};
+use super::output::Output;
+
+// Window, keyboard and hotkeys around a `Gameboy`.
+pub struct Frontend {
+    pub gameboy: Gameboy,
+    output: Output,
+    fast_forward_speed: Speed,
+    slow_motion_speed: Speed,
+    // Save state slot used by the hotkeys, 0-9.
+    pub state_slot: u8,
+    // Frame rate shown in the title.
+    fps: f64,
+    debugger: Debugger,
+    // Enter the debugger before the next frame.
+    debug_requested: bool,
+    // Paces emulation with `Pacing::Audio` when set.
+    audio: Option<Box<dyn AudioQueue>>,
+    // One frame of samples for `audio`.
+    samples: Vec<i16>,
+}
+
+impl Frontend {
+    pub fn new(fname: &String, config: &Config) -> Self {
+        let mut gameboy = Gameboy::new(fname);
+        gameboy.set_run_ahead(config.run_ahead);
+        if config.rewind_budget > 0 {
+            gameboy.enable_rewind(config.rewind_interval, config.rewind_budget);
+        }
+
+        Frontend {
+            gameboy,
+            output: Output::new(config),
+            fast_forward_speed: config.fast_forward_speed,
+            slow_motion_speed: config.slow_motion_speed,
+            state_slot: 0,
+            fps: 0.0,
+            debugger: Debugger::new(),
+            debug_requested: false,
+            audio: None,
+            samples: Vec::new(),
+        }
+    }
+
+    // Paces emulation on `audio` instead of sleeping between frames.
+    pub fn set_audio(&mut self, audio: Box<dyn AudioQueue>) {
+        // The APU isn't implemented yet, so every frame is silent.
+        self.samples = vec![0; limiter::samples_per_frame(audio.sample_rate())];
+        self.audio = Some(audio);
+        self.gameboy.set_pacing(Pacing::Audio);
+    }
+
+    // Returns false when the window is closed, the quit hotkey is pressed or the debugger quits.
+    pub fn exec_frame(&mut self) -> bool {
+        if !self.handle_hotkeys() {
+            return false;
+        }
+
+        // The debugger reads commands from the terminal while the window waits.
+        let reason = self.gameboy.take_break();
+        if reason.is_some() || self.debug_requested {
+            self.debug_requested = false;
+            if let DebugAction::Quit = self.debugger.repl(&mut self.gameboy, reason.as_deref()) {
+                return false;
+            }
+            self.output.write_screen(self.gameboy.screen());
+            self.gameboy.limiter.resync();
+        }
+
+        // Live input would desync a movie being played back.
+        let keys = self.output.handle_keys();
+        if !self.gameboy.is_playing_movie() {
+            for (button, down) in keys {
+                if down {
+                    self.gameboy.press(button);
+                } else {
+                    self.gameboy.release(button);
+                }
+            }
+        }
+
+        self.gameboy.set_rewinding(self.output.is_hotkey_down(Hotkey::Rewind));
+        if self.gameboy.exec_frame() {
+            self.output.write_screen(self.gameboy.screen());
+            if let Some(audio) = &mut self.audio {
+                if self.gameboy.limiter.audio_paced() {
+                    audio.push_frame(&self.samples);
+                }
+            }
+        } else {
+            self.output.update();
+        }
+
+        let fps = self.gameboy.fps();
+        if fps != self.fps {
+            self.fps = fps;
+            self.output.set_title(&format!("deepboy - {:.1} fps", fps));
+        }
+
+        self.window_is_open()
+    }
+
+    // Enters the debugger before the next frame, like the debug hotkey.
+    pub fn request_debugger(&mut self) {
+        self.debug_requested = true;
+    }
+
+    pub fn window_is_open(&self) -> bool {
+        self.output.window_is_open()
+    }
+
+    // Returns false when the quit hotkey is pressed.
+    fn handle_hotkeys(&mut self) -> bool {
+        let gameboy = &mut self.gameboy;
+        for hotkey in self.output.handle_hotkeys() {
+            match hotkey {
+                Hotkey::Pause => if gameboy.is_paused() { gameboy.resume() } else { gameboy.pause() },
+                Hotkey::FrameAdvance => gameboy.advance_frame(),
+                // Resetting would desync a movie.
+                Hotkey::Reset => if gameboy.movie.is_none() { gameboy.reset() },
+                Hotkey::Screenshot => {
+                    let fname = format!("deepboy_{}.png", gameboy.elapsed_cycles);
+                    match gameboy.save_screenshot(&fname) {
+                        Ok(()) => println!("screenshot: {}", fname),
+                        Err(e) => eprintln!("screenshot failed: {}", e),
+                    }
+                }
+                Hotkey::Debug => self.debug_requested = true,
+                Hotkey::Quit => return false,
+                // Fast-forward and rewind are active while the key is held.
+                Hotkey::FastForward | Hotkey::Rewind => {},
+                Hotkey::SlowMotion => {
+                    let speed = if gameboy.speed() == Speed::NORMAL { self.slow_motion_speed } else { Speed::NORMAL };
+                    gameboy.set_speed(speed);
+                }
+                Hotkey::SaveState => match gameboy.save_slot(self.state_slot) {
+                    Ok(()) => println!("state: saved slot {}", self.state_slot),
+                    Err(e) => eprintln!("state: saving slot {} failed: {}", self.state_slot, e),
+                },
+                // Loading a state would desync a movie.
+                Hotkey::LoadState => if gameboy.movie.is_none() {
+                    match gameboy.load_slot(self.state_slot) {
+                        Ok(()) => println!("state: loaded slot {}", self.state_slot),
+                        Err(e) => eprintln!("state: loading slot {} failed: {}", self.state_slot, e),
+                    }
+                },
+                Hotkey::NextSlot => {
+                    self.state_slot = (self.state_slot + 1) % 10;
+                    println!("state: slot {}", self.state_slot);
+                }
+                Hotkey::PreviousSlot => {
+                    self.state_slot = (self.state_slot + 9) % 10;
+                    println!("state: slot {}", self.state_slot);
+                }
+            }
+        }
+
+        let speed = if self.output.is_hotkey_down(Hotkey::FastForward) { self.fast_forward_speed } else { gameboy.speed() };
+        gameboy.limiter.set_speed(speed);
+        true
+    }
+}
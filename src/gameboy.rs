@@ -1,47 +1,531 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{fs, io, path::{Path, PathBuf}};
 
+use crate::bess;
+use crate::cpu::CpuEvent;
+use crate::debugger::Breakpoints;
+use crate::defs::{CLOCKS_PER_FRAME, GAMEBOY_HEIGHT, GAMEBOY_WIDTH, Color};
+use crate::joypad::Button;
+use crate::movie::{Movie, MovieError, MovieStart};
+use crate::rewind::Rewind;
+use crate::png;
+use crate::limiter::{FrameLimiter, Pacing, Speed};
 use crate::rtc::RTC;
+use crate::state::{self, StateError, StateReader, StateWriter};
 use super::mmc::MMC;
-use super::output::Output;
 
+#[derive(Clone)]
+pub enum MovieState {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+// The emulator core. It owns the whole machine, so instances can be cloned
+// to branch a state and sent to other threads. The window lives in `frontend::Frontend`.
+#[derive(Clone)]
 pub struct Gameboy {
-    pub mmc: Rc<RefCell<MMC>>,
+    pub mmc: MMC,
     pub cpu: RTC,
-    pub elapsed_cycles: u32,
-    pub output: Output,
+    pub elapsed_cycles: u64,
+    pub paused: bool,
+    // Frames to run while paused.
+    advance_frames: u32,
+    speed: Speed,
+    pub limiter: FrameLimiter,
+    // Frames emulated ahead of the displayed one, 0-4.
+    run_ahead: u32,
+    // None when rewind is disabled.
+    pub rewind: Option<Rewind>,
+    rewinding: bool,
+    pub movie: Option<MovieState>,
+    // Last completed frame as RGB24.
+    screen: Vec<u8>,
+    rom_name: String,
+    pub breakpoints: Breakpoints,
+    // Why `run_frame` stopped early, until the frontend takes it.
+    break_reason: Option<String>,
+    // Input for the current frame is latched but the frame isn't complete yet,
+    // e.g. because a breakpoint interrupted it.
+    mid_frame: bool,
+    // Cycles run in the current frame, which ends after CLOCKS_PER_FRAME while the LCD is off.
+    frame_cycles: u32,
+    // Movie playback ran out of frames, until the frontend takes it.
+    movie_finished: bool,
 }
 
+const _: fn() = || {
+    fn assert_send_clone<T: Send + Clone>() {}
+    assert_send_clone::<Gameboy>();
+};
+
 impl Gameboy {
+    // Runs at real-time speed.
     pub fn new(fname: &String) -> Self {
-        let mmc = Rc::new(RefCell::new(MMC::new(fname)));
-        let cpu = RTC::new(mmc.clone());
-        let output = Output::new(mmc.clone());
+        let mut gameboy = Gameboy::headless(fname);
+        gameboy.set_speed(Speed::NORMAL);
+        gameboy
+    }
+
+    // Runs as fast as possible.
+    pub fn headless(fname: &String) -> Self {
+        let mut limiter = FrameLimiter::new();
+        limiter.set_speed(Speed::Uncapped);
 
         Gameboy {
-            mmc: mmc,
-            cpu: cpu,
+            mmc: MMC::new(fname),
+            cpu: RTC::new(),
             elapsed_cycles: 0,
-            output,
+            paused: false,
+            advance_frames: 0,
+            speed: Speed::Uncapped,
+            limiter,
+            run_ahead: 0,
+            rewind: None,
+            rewinding: false,
+            movie: None,
+            screen: vec![0xFF; GAMEBOY_WIDTH * GAMEBOY_HEIGHT * 3],
+            rom_name: fname.clone(),
+            breakpoints: Breakpoints::default(),
+            break_reason: None,
+            mid_frame: false,
+            frame_cycles: 0,
+            movie_finished: false,
         }
     }
 
+    // Power cycles the machine with the same ROM.
+    pub fn reset(&mut self) {
+        let watchpoints = std::mem::take(&mut self.mmc.watchpoints);
+        self.mmc = MMC::new(&self.rom_name);
+        self.mmc.watchpoints = watchpoints;
+        self.cpu = RTC::new();
+        self.elapsed_cycles = 0;
+        self.mid_frame = false;
+        self.frame_cycles = 0;
+        self.clear_rewind();
+    }
+
+    // History recorded before the machine state was replaced would replay on the wrong state.
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    // Runs one frame and returns true, or returns false without running while paused.
     pub fn exec_frame(&mut self) -> bool {
-        let cycles = self.cpu.run() * 4;
-        self.elapsed_cycles += cycles;
+        if self.paused && self.advance_frames == 0 {
+            self.limiter.idle();
+            return false;
+        }
+        self.advance_frames = self.advance_frames.saturating_sub(1);
+
+        // Rewinding would desync a movie.
+        if self.mid_frame {
+            self.run_frame();
+        } else if self.rewinding && self.movie.is_none() {
+            self.rewind_frame();
+        } else {
+            self.begin_frame();
+            if self.run_ahead > 0 {
+                self.run_frame_ahead();
+            } else {
+                self.run_frame();
+            }
+        }
+
+        self.limiter.wait();
+        true
+    }
+
+    // Takes a rewind snapshot when one is due and latches the input of the frame about to run.
+    fn begin_frame(&mut self) {
+        if let Some(rewind) = &self.rewind {
+            if rewind.snapshot_due() {
+                let state = self.save_state();
+                self.rewind.as_mut().unwrap().push_snapshot(state);
+            }
+        }
+        self.latch_input();
+        self.mid_frame = true;
+    }
+
+    // Keeps a snapshot every `interval` frames in at most `budget` bytes.
+    pub fn enable_rewind(&mut self, interval: u32, budget: usize) {
+        self.rewind = Some(Rewind::new(interval, budget));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    // While set, `exec_frame` steps one frame back instead of forward.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
+    // Goes back one frame. Returns false when there is no more history.
+    pub fn rewind_frame(&mut self) -> bool {
+        let (state, inputs) = match self.rewind.as_mut().and_then(|rewind| rewind.step_back()) {
+            Some((state, inputs)) => (state.to_vec(), inputs.to_vec()),
+            None => return false,
+        };
+
+        self.restore_state(&state).expect("Rewind: loading a snapshot failed.");
+        // Replay up to the target frame.
+        for input in inputs {
+            self.mmc.set_joypad_state(input);
+            self.run_hidden_frame();
+        }
+        true
+    }
+
+    // Shows the result of the current input `frames` frames early, hiding the game's own input lag.
+    // Each frame is then emulated `frames + 1` times.
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        assert!(frames <= 4, "Gameboy: run-ahead is limited to 4 frames.");
+        self.run_ahead = frames;
+    }
+
+    pub fn run_ahead(&self) -> u32 {
+        self.run_ahead
+    }
+
+    // Runs the real frame, then runs ahead with the same input from a snapshot,
+    // keeps the last screen and restores the snapshot.
+    fn run_frame_ahead(&mut self) {
+        self.run_frame();
+        if self.mid_frame {
+            return;
+        }
+        let snapshot = self.save_state();
+        let event = self.cpu.cpu.event;
+
+        for _ in 0..self.run_ahead {
+            self.run_hidden_frame();
+        }
+
+        let screen = self.screen.clone();
+        self.restore_state(&snapshot).expect("Run-ahead: restoring the snapshot failed.");
+        self.screen = screen;
+        self.cpu.cpu.event = event;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.advance_frames = 0;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Pauses and runs a single frame on the next `exec_frame`.
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance_frames += 1;
+    }
+
+    // Emulation speed relative to real time. Headless instances start uncapped.
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.limiter.set_speed(speed);
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
 
-        self.mmc.borrow_mut().timer.run(cycles);
-        self.mmc.borrow_mut().ppu.run(cycles);
-        if self.mmc.borrow_mut().ppu.v_blank {
-            self.mmc.borrow_mut().ppu.v_blank = false;
-            self.output.write_screen();
+    // Pacing against audio leaves throttling to a blocking audio queue in the frontend.
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.limiter.set_pacing(pacing);
+    }
+
+    // Frames per second measured over the last second.
+    pub fn fps(&self) -> f64 {
+        self.limiter.fps()
+    }
+
+    // Runs until the current frame is complete, see `poll_frame`.
+    // Stops early at a breakpoint or watchpoint, see `take_break`.
+    pub fn run_frame(&mut self) {
+        self.mid_frame = true;
+        loop {
+            let pc = self.cpu.cpu.regs.pc;
+            self.step();
+            let completed = self.poll_frame();
+            if let Some(reason) = self.check_break(pc) {
+                self.break_reason = Some(reason);
+                return;
+            }
+            if completed {
+                return;
+            }
         }
+    }
 
-        self.output.handle_keys();
+    // Frames nobody looks at, replayed by rewind or run ahead, ignore breakpoints and watchpoints.
+    fn run_hidden_frame(&mut self) {
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let watchpoints = std::mem::take(&mut self.mmc.watchpoints);
+        self.run_frame();
+        self.breakpoints = breakpoints;
+        self.mmc.watchpoints = watchpoints;
+    }
+
+    // Why execution should stop after the instruction at `pc`, if it should.
+    pub fn check_break(&mut self, pc: u16) -> Option<String> {
+        if let Some(hit) = self.mmc.watch_hit.take() {
+            return Some(hit.describe(pc, self.elapsed_cycles));
+        }
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+        self.breakpoints.check(&self.cpu.cpu, &self.mmc)
+    }
+
+    // Takes the frame out of the PPU once it is complete, or ends it after a frame's worth
+    // of cycles while the LCD is off. Returns true if the frame ended.
+    pub fn poll_frame(&mut self) -> bool {
+        if self.mmc.ppu.v_blank {
+            self.mmc.ppu.v_blank = false;
+            self.update_screen();
+        } else if self.frame_cycles < CLOCKS_PER_FRAME || self.mmc.ppu.lcd_enabled() {
+            return false;
+        }
+        self.mid_frame = false;
+        self.frame_cycles = 0;
+        true
+    }
+
+    // Why emulation stopped at a breakpoint since the last call, if it did.
+    pub fn take_break(&mut self) -> Option<String> {
+        self.break_reason.take()
+    }
+
+    // Executes a single instruction and returns the elapsed cycles.
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.cpu.run(&mut self.mmc);
+        self.elapsed_cycles += u64::from(cycles);
+        self.frame_cycles = self.frame_cycles.saturating_add(cycles);
+        cycles
+    }
 
-        if self.output.window_is_open() {
-            true
+    // Like `step`, but begins a frame first when none is running, so a debugger stepping
+    // across frames latches input for movies and rewind just like `exec_frame`.
+    pub fn step_in_frame(&mut self) -> u32 {
+        if !self.mid_frame {
+            self.begin_frame();
+        }
+        self.step()
+    }
+
+    fn update_screen(&mut self) {
+        for (i, pixel) in self.mmc.ppu.frame_buffer.iter().flatten().enumerate() {
+            let color = Color::from_shade(pixel[0]) as u8;
+            self.screen[i * 3..i * 3 + 3].copy_from_slice(&[color, color, color]);
+        }
+        self.mmc.ppu.reset_buffer();
+    }
+
+    // Last completed frame as RGB24, row by row.
+    pub fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    pub fn save_screenshot(&self, fname: &str) -> io::Result<()> {
+        png::write(fname, GAMEBOY_WIDTH, GAMEBOY_HEIGHT, &self.screen)
+    }
+
+    // Unlike the CPU, sees VRAM and OAM even while the PPU or OAM DMA holds them.
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.mmc.peek(addr)
+    }
+
+    // Input only changes on frame boundaries so that a movie replays exactly.
+    fn latch_input(&mut self) {
+        if let Some(MovieState::Playing { movie, frame }) = &mut self.movie {
+            match movie.frames.get(*frame) {
+                Some(state) => {
+                    let state = *state;
+                    *frame += 1;
+                    self.mmc.set_joypad_state(state);
+                    return;
+                }
+                None => {
+                    self.movie = None;
+                    self.movie_finished = true;
+                }
+            }
+        }
+
+        let state = self.mmc.joypad.state();
+        if let Some(MovieState::Recording(movie)) = &mut self.movie {
+            movie.frames.push(state);
+        }
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push_input(state);
+        }
+    }
+
+    // Whether movie playback finished since the last call.
+    pub fn take_movie_finished(&mut self) -> bool {
+        std::mem::replace(&mut self.movie_finished, false)
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Playing { .. }))
+    }
+
+    // Snapshot of the whole machine: a versioned header with the ROM crc32,
+    // followed by CPU, MMC (with PPU, timer, joypad, DMA and mapper) and the last frame.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        state::write_header(&mut w, self.rom_crc32());
+        self.cpu.cpu.save_state(&mut w);
+        self.mmc.save_state(&mut w);
+        w.write_u64(self.elapsed_cycles);
+        w.write_bytes(&self.screen);
+        w.into_bytes()
+    }
+
+    // Leaves the machine untouched if the state can't be loaded. Clears the rewind history.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        self.restore_state(data)?;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Like `load_state`, but keeps the rewind history, for snapshots taken by rewind and run-ahead.
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        let rom_crc32 = state::read_header(&mut r)?;
+        if rom_crc32 != self.rom_crc32() {
+            return Err(StateError::RomMismatch { expected: rom_crc32, found: self.rom_crc32() });
+        }
+
+        let backup = self.save_state();
+        if let Err(e) = self.read_state(&mut r) {
+            let mut r = StateReader::new(&backup);
+            state::read_header(&mut r).unwrap();
+            self.read_state(&mut r).expect("Restoring the previous state failed.");
+            return Err(e);
+        }
+        // States are taken between frames.
+        self.mid_frame = false;
+        self.frame_cycles = 0;
+        Ok(())
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.cpu.load_state(r)?;
+        self.mmc.load_state(r)?;
+        self.elapsed_cycles = r.read_u64()?;
+        r.read_bytes(&mut self.screen)?;
+        if !r.is_empty() {
+            return Err(StateError::Format("trailing data"));
+        }
+        Ok(())
+    }
+
+    // Exports a BESS state that other emulators such as SameBoy can read.
+    pub fn export_bess(&self) -> Vec<u8> {
+        bess::export(&self.cpu.cpu, &self.mmc)
+    }
+
+    // Leaves the machine untouched if the state can't be imported.
+    pub fn import_bess(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        let result = bess::import(&mut self.cpu.cpu, &mut self.mmc, data);
+        if let Err(e) = result {
+            self.restore_state(&backup).expect("Restoring the previous state failed.");
+            return Err(e);
+        }
+        self.mid_frame = false;
+        self.frame_cycles = 0;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Loads either a deepboy or a BESS state file.
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), StateError> {
+        let data = fs::read(path)?;
+        if data.starts_with(state::MAGIC) {
+            self.load_state(&data)
         } else {
-            false
+            self.import_bess(&data)
         }
     }
+
+    // Slot files live next to the ROM, e.g. `zelda.ss1`.
+    pub fn slot_path(&self, slot: u8) -> PathBuf {
+        Path::new(&self.rom_name).with_extension(format!("ss{}", slot))
+    }
+
+    pub fn save_slot(&self, slot: u8) -> io::Result<()> {
+        fs::write(self.slot_path(slot), self.save_state())
+    }
+
+    pub fn load_slot(&mut self, slot: u8) -> Result<(), StateError> {
+        let data = fs::read(self.slot_path(slot))?;
+        self.load_state(&data)
+    }
+
+    pub fn rom_crc32(&self) -> u32 {
+        self.mmc.rom.crc32
+    }
+
+    // Resets the machine and records the input of every following frame.
+    pub fn start_recording(&mut self) {
+        self.reset();
+        self.movie = Some(MovieState::Recording(Movie::new(self.rom_crc32(), MovieStart::PowerOn)));
+    }
+
+    // Records from the current state instead of power-on.
+    pub fn start_recording_from_state(&mut self) {
+        let start = MovieStart::SaveState(self.save_state());
+        self.movie = Some(MovieState::Recording(Movie::new(self.rom_crc32(), start)));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieState::Recording(movie)) => Some(movie),
+            other => {
+                self.movie = other;
+                None
+            }
+        }
+    }
+
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_crc32 != self.rom_crc32() {
+            return Err(MovieError::RomMismatch { expected: movie.rom_crc32, found: self.rom_crc32() });
+        }
+
+        match &movie.start {
+            MovieStart::PowerOn => self.reset(),
+            MovieStart::SaveState(data) => self.load_state(data).map_err(MovieError::State)?,
+        }
+        self.movie = Some(MovieState::Playing { movie, frame: 0 });
+        Ok(())
+    }
+
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        self.mmc.set_buttons(buttons);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmc.press(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmc.release(button);
+    }
+
+    pub fn poll_event(&mut self) -> Option<CpuEvent> {
+        self.cpu.cpu.take_event()
+    }
 }
\ No newline at end of file
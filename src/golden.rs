@@ -0,0 +1,154 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use crate::defs::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH};
+use crate::gameboy::Gameboy;
+use crate::png;
+use crate::rom::crc32;
+use crate::script::{Script, ScriptError};
+
+// One golden-frame scene: a ROM run headless for a number of frames,
+// after an optional input script, compared against `<name>.png`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoldenCase {
+    pub name: String,
+    pub rom: PathBuf,
+    pub frames: u32,
+    pub script: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(io::Error),
+    Script(ScriptError),
+    Parse { line: usize, msg: String },
+    Size { width: usize, height: usize },
+    // `pixels` differ, the actual frame and a diff image were written next to `diff`.
+    Mismatch { pixels: usize, hash: u32, diff: PathBuf },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoldenError::Io(e) => write!(f, "{}", e),
+            GoldenError::Script(e) => write!(f, "script: {}", e),
+            GoldenError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            GoldenError::Size { width, height } => {
+                write!(f, "reference is {}x{}, expected {}x{}", width, height, GAMEBOY_WIDTH, GAMEBOY_HEIGHT)
+            }
+            GoldenError::Mismatch { pixels, hash, diff } => {
+                write!(f, "{} pixels differ (frame hash {:08x}), see {}", pixels, hash, diff.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+impl From<io::Error> for GoldenError {
+    fn from(e: io::Error) -> Self {
+        GoldenError::Io(e)
+    }
+}
+
+impl From<ScriptError> for GoldenError {
+    fn from(e: ScriptError) -> Self {
+        GoldenError::Script(e)
+    }
+}
+
+// Reads a manifest with one case per line, `#` starts a comment:
+//
+//   dmg-acid2  dmg-acid2.gb  400
+//   menu       homebrew.gb   60  open_menu.txt
+//
+// ROM and script paths are relative to the manifest.
+pub fn load_cases(manifest: &Path) -> Result<Vec<GoldenCase>, GoldenError> {
+    let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let mut cases = Vec::new();
+    for (i, raw) in fs::read_to_string(manifest)?.lines().enumerate() {
+        let line = i + 1;
+        let words: Vec<&str> = raw.split('#').next().unwrap_or("").split_whitespace().collect();
+        let (name, rom, frames, script) = match words.as_slice() {
+            [] => continue,
+            [name, rom, frames] => (name, rom, frames, None),
+            [name, rom, frames, script] => (name, rom, frames, Some(dir.join(script))),
+            _ => return Err(GoldenError::Parse { line, msg: "expected `name rom frames [script]`".to_string() }),
+        };
+        let frames = frames.parse()
+            .map_err(|_| GoldenError::Parse { line, msg: format!("invalid frame count `{}`", frames) })?;
+        cases.push(GoldenCase { name: name.to_string(), rom: dir.join(rom), frames, script });
+    }
+    Ok(cases)
+}
+
+// Runs the script, then `frames` more frames, and returns the last frame as RGB24.
+pub fn render(case: &GoldenCase) -> Result<Vec<u8>, GoldenError> {
+    let mut gameboy = Gameboy::headless(&case.rom.to_string_lossy().into_owned());
+    if let Some(script) = &case.script {
+        Script::load(script)?.run(&mut gameboy)?;
+    }
+    for _ in 0..case.frames {
+        gameboy.exec_frame();
+    }
+    Ok(gameboy.screen().to_vec())
+}
+
+// Shade 0 (lightest) to 3 of a gray RGB pixel. Comparing shades lets references
+// drawn with another palette, like dmg-acid2's, match our colors.
+fn shade(rgb: &[u8]) -> u8 {
+    ((255 - u32::from(rgb[0]) + 42) / 85) as u8
+}
+
+// Marks the pixels whose shade differs.
+pub fn compare(actual: &[u8], reference: &[u8]) -> Vec<bool> {
+    actual.chunks(3).zip(reference.chunks(3)).map(|(a, r)| shade(a) != shade(r)).collect()
+}
+
+// The actual frame faded, with differing pixels in red.
+pub fn diff_image(actual: &[u8], mismatches: &[bool]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(actual.len());
+    for (pixel, &mismatch) in actual.chunks(3).zip(mismatches) {
+        if mismatch {
+            out.extend_from_slice(&[0xFF, 0x00, 0x00]);
+        } else {
+            let v = 0xC0 + pixel[0] / 4;
+            out.extend_from_slice(&[v, v, v]);
+        }
+    }
+    out
+}
+
+// crc32 over the shades of a frame, stable across palettes.
+pub fn frame_hash(rgb: &[u8]) -> u32 {
+    let shades: Vec<u8> = rgb.chunks(3).map(shade).collect();
+    crc32(&shades)
+}
+
+// Renders the case and compares it with `reference`. On mismatch the frame is written to
+// `<out_dir>/<name>.actual.png` and the differences to `<out_dir>/<name>.diff.png`.
+pub fn check(case: &GoldenCase, reference: &Path, out_dir: &Path) -> Result<(), GoldenError> {
+    let (width, height, expected) = png::read(reference)?;
+    if (width, height) != (GAMEBOY_WIDTH, GAMEBOY_HEIGHT) {
+        return Err(GoldenError::Size { width, height });
+    }
+
+    let actual = render(case)?;
+    let mismatches = compare(&actual, &expected);
+    let pixels = mismatches.iter().filter(|&&m| m).count();
+    if pixels == 0 {
+        return Ok(());
+    }
+
+    fs::create_dir_all(out_dir)?;
+    let diff = out_dir.join(format!("{}.diff.png", case.name));
+    png::write(out_dir.join(format!("{}.actual.png", case.name)), GAMEBOY_WIDTH, GAMEBOY_HEIGHT, &actual)?;
+    png::write(&diff, GAMEBOY_WIDTH, GAMEBOY_HEIGHT, &diff_image(&actual, &mismatches))?;
+    Err(GoldenError::Mismatch { pixels, hash: frame_hash(&actual), diff })
+}
+
+// Renders the case and stores the frame as its new reference.
+pub fn bless(case: &GoldenCase, reference: &Path) -> Result<(), GoldenError> {
+    let actual = render(case)?;
+    png::write(reference, GAMEBOY_WIDTH, GAMEBOY_HEIGHT, &actual)?;
+    Ok(())
+}
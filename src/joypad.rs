@@ -1,9 +1,62 @@
-use std::{cell::RefCell, rc::Rc};
-
 use crate::register::ByteRegister;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        let button = match name.to_ascii_lowercase().as_str() {
+            "a" => Button::A,
+            "b" => Button::B,
+            "select" => Button::Select,
+            "start" => Button::Start,
+            "up" => Button::Up,
+            "down" => Button::Down,
+            "left" => Button::Left,
+            "right" => Button::Right,
+            _ => return None,
+        };
+        Some(button)
+    }
 
+    // Bit of the button in the byte returned by `Joypad::state`.
+    pub fn bit(&self) -> u8 {
+        match self {
+            Button::A => 0,
+            Button::B => 1,
+            Button::Select => 2,
+            Button::Start => 3,
+            Button::Right => 4,
+            Button::Left => 5,
+            Button::Up => 6,
+            Button::Down => 7,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Joypad {
-    int_flag: Rc<RefCell<ByteRegister>>,
     select_switch: ByteRegister,
     right: bool,
     left: bool,
@@ -16,10 +69,11 @@ pub struct Joypad {
 }
 
 impl Joypad {
-    pub fn new(int_flag: Rc<RefCell<ByteRegister>>) -> Self {
+    pub fn new() -> Self {
+        let mut select_switch = ByteRegister::new();
+        select_switch.set(0x30);
         Joypad {
-            int_flag: int_flag,
-            select_switch: ByteRegister::new(),
+            select_switch,
             right: false,
             left: false,
             up: false,
@@ -31,61 +85,132 @@ impl Joypad {
         }
     }
 
-    pub fn key_down(&mut self, key: minifb::Key) {
-        self.int_flag.borrow_mut().set_bit(4, true);
-        match key {
-            minifb::Key::Right => { self.right = true },
-            minifb::Key::Left => { self.left = true },
-            minifb::Key::Up => { self.up = true },
-            minifb::Key::Down => { self.down = true },
-            minifb::Key::A => { self.a = true },
-            minifb::Key::B => { self.b = true },
-            minifb::Key::Space => { self.select = true },
-            minifb::Key::Enter => { self.start = true },
-            _ => {}
-        } 
-    }
-
-    pub fn key_up(&mut self, key: minifb::Key) {
-        match key {
-            minifb::Key::Right => { self.right = false },
-            minifb::Key::Left => { self.left = false },
-            minifb::Key::Up => { self.up = false },
-            minifb::Key::Down => { self.down = false },
-            minifb::Key::A => { self.a = false },
-            minifb::Key::B => { self.b = false },
-            minifb::Key::Space => { self.select = false },
-            minifb::Key::Enter => { self.start = false },
-            _ => {}
-        } 
+    pub fn press(&mut self, button: Button, int_flag: &mut ByteRegister) {
+        let prev = self.lines();
+        self.set(button, true);
+        self.check_interrupt(prev, int_flag);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set(button, false);
+    }
+
+    // Presses exactly the given buttons and releases the others.
+    pub fn set_buttons(&mut self, buttons: &[Button], int_flag: &mut ByteRegister) {
+        let prev = self.lines();
+        for button in Button::ALL.iter() {
+            self.set(*button, buttons.contains(button));
+        }
+        self.check_interrupt(prev, int_flag);
+    }
+
+    // Pressed buttons as a bitmask, see `Button::bit`.
+    pub fn state(&self) -> u8 {
+        let mut state = ByteRegister::new();
+        for button in Button::ALL.iter() {
+            state.set_bit(button.bit(), self.is_pressed(*button));
+        }
+        state.get()
+    }
+
+    pub fn set_state(&mut self, state: u8, int_flag: &mut ByteRegister) {
+        let prev = self.lines();
+        for button in Button::ALL.iter() {
+            self.set(*button, state & (1 << button.bit()) != 0);
+        }
+        self.check_interrupt(prev, int_flag);
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::Right => self.right,
+            Button::Left => self.left,
+            Button::Up => self.up,
+            Button::Down => self.down,
+            Button::A => self.a,
+            Button::B => self.b,
+            Button::Select => self.select,
+            Button::Start => self.start,
+        }
+    }
+
+    fn set(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Right => self.right = pressed,
+            Button::Left => self.left = pressed,
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::A => self.a = pressed,
+            Button::B => self.b = pressed,
+            Button::Select => self.select = pressed,
+            Button::Start => self.start = pressed,
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.select_switch.get());
+        w.write_u8(self.state());
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.select_switch.set(r.read_u8()?);
+        let state = r.read_u8()?;
+        for button in Button::ALL.iter() {
+            self.set(*button, state & (1 << button.bit()) != 0);
+        }
+        Ok(())
     }
 
     pub fn read(&self, addr: u16) -> u8 {
         assert_eq!(addr, 0xff00);
-        let mut keys = ByteRegister::new();
-        keys.set(0b1111);
+        // Bits 6-7 are unused and always read as 1.
+        0xC0 | (self.select_switch.get() & 0x30) | self.lines()
+    }
+
+    pub fn write(&mut self, addr: u16, dat: u8, int_flag: &mut ByteRegister) {
+        assert_eq!(addr, 0xff00);
+        // Only the select lines (bits 4-5) are writable.
+        let prev = self.lines();
+        self.select_switch.set(dat & 0x30);
+        self.check_interrupt(prev, int_flag);
+    }
+
+    // Lower nibble of P1. Lines are active low, and both groups
+    // are combined when both select lines are low.
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
 
         if !self.select_switch.check_bit(4) {
+            let mut keys = ByteRegister::new();
             keys.set_bit(0, !self.right);
             keys.set_bit(1, !self.left);
             keys.set_bit(2, !self.up);
             keys.set_bit(3, !self.down);
-            return self.select_switch.data | keys.get();
+            lines &= keys.get();
         }
 
         if !self.select_switch.check_bit(5) {
+            let mut keys = ByteRegister::new();
             keys.set_bit(0, !self.a);
             keys.set_bit(1, !self.b);
             keys.set_bit(2, !self.select);
             keys.set_bit(3, !self.start);
-            return self.select_switch.data | keys.get();
+            lines &= keys.get();
         }
 
-        return self.select_switch.data;
+        lines
     }
 
-    pub fn write(&mut self, addr: u16, dat: u8) {
-        assert_eq!(addr, 0xff00);
-        self.select_switch.set(dat);
+    // The joypad interrupt fires on a high-to-low transition of any input line.
+    fn check_interrupt(&self, prev: u8, int_flag: &mut ByteRegister) {
+        if prev & !self.lines() != 0 {
+            int_flag.set_bit(4, true);
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
     }
 }
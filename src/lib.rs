@@ -1,7 +1,12 @@
 pub mod gameboy;
+pub mod debugger;
+pub mod disasm;
+pub mod frontend;
 pub mod rom;
+pub mod bus;
 pub mod cpu;
 pub mod rtc;
+pub mod limiter;
 pub mod ppu;
 pub mod apu;
 pub mod timer;
@@ -10,4 +15,15 @@ pub mod mmc;
 pub mod output;
 pub mod defs;
 pub mod joypad;
-pub mod mapper;
\ No newline at end of file
+pub mod mapper;
+pub mod dma;
+pub mod serial;
+pub mod config;
+pub mod movie;
+pub mod png;
+pub mod golden;
+pub mod script;
+pub mod testrom;
+pub mod state;
+pub mod bess;
+pub mod rewind;
\ No newline at end of file
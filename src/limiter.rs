@@ -0,0 +1,173 @@
+use std::{hint, thread, time::{Duration, Instant}};
+use crate::defs::{CLOCKS_PER_FRAME, CLOCK_RATE};
+
+// Sleeping is only accurate to a millisecond or so, the rest of the wait is spent spinning.
+const SPIN_TIME: Duration = Duration::from_micros(1500);
+// Falling further behind than this resets the schedule instead of rushing to catch up.
+const MAX_LAG_FRAMES: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    // Multiple of real time: above 1.0 is fast-forward, below is slow motion.
+    Factor(f64),
+    // As fast as possible.
+    Uncapped,
+}
+
+impl Speed {
+    pub const NORMAL: Speed = Speed::Factor(1.0);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    // Sleep until the next frame is due.
+    Video,
+    // Don't wait at normal speed, the frontend is throttled by a blocking `AudioQueue` instead.
+    // Other speeds fall back to video pacing.
+    Audio,
+}
+
+// Audio output a frontend paces against with `Pacing::Audio`.
+pub trait AudioQueue {
+    fn sample_rate(&self) -> u32;
+    // Queues the mono samples of one frame, blocking while the device has enough buffered.
+    fn push_frame(&mut self, samples: &[i16]);
+}
+
+// Samples in one frame at `sample_rate`.
+pub fn samples_per_frame(sample_rate: u32) -> usize {
+    (f64::from(sample_rate) * frame_secs()).round() as usize
+}
+
+// Paces emulation on completed frames of CLOCKS_PER_FRAME cycles (~59.73 Hz).
+#[derive(Clone)]
+pub struct FrameLimiter {
+    speed: Speed,
+    pacing: Pacing,
+    // When the next frame is due.
+    deadline: Instant,
+    // How late the last frame was finished compared to its deadline.
+    drift: Duration,
+    fps: f64,
+    fps_frames: u32,
+    fps_start: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        FrameLimiter {
+            speed: Speed::NORMAL,
+            pacing: Pacing::Video,
+            deadline: now,
+            drift: Duration::ZERO,
+            fps: 0.0,
+            fps_frames: 0,
+            fps_start: now,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        if let Speed::Factor(factor) = speed {
+            assert!(factor > 0.0, "FrameLimiter: speed factor must be positive.");
+        }
+        if speed != self.speed {
+            self.speed = speed;
+            self.resync();
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+        self.resync();
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    // Whether the frontend's audio queue paces the current frame instead of `wait`.
+    pub fn audio_paced(&self) -> bool {
+        self.pacing == Pacing::Audio && self.speed == Speed::NORMAL
+    }
+
+    // Real time taken by one frame at the current speed, None when uncapped.
+    pub fn frame_time(&self) -> Option<Duration> {
+        match self.speed {
+            Speed::Factor(factor) => Some(Duration::from_secs_f64(frame_secs() / factor)),
+            Speed::Uncapped => None,
+        }
+    }
+
+    // Starts a new schedule from now, e.g. after a pause or a speed change.
+    pub fn resync(&mut self) {
+        self.deadline = Instant::now();
+        self.drift = Duration::ZERO;
+    }
+
+    // Waits until the frame just emulated is due and returns the measured frame rate once per second.
+    pub fn wait(&mut self) -> Option<f64> {
+        if self.audio_paced() {
+            self.resync();
+        } else if let Some(frame_time) = self.frame_time() {
+            self.deadline += frame_time;
+
+            let now = Instant::now();
+            if self.deadline > now {
+                let remaining = self.deadline - now;
+                if remaining > SPIN_TIME {
+                    thread::sleep(remaining - SPIN_TIME);
+                }
+                while Instant::now() < self.deadline {
+                    hint::spin_loop();
+                }
+            }
+
+            self.drift = Instant::now().saturating_duration_since(self.deadline);
+            if self.drift > frame_time * MAX_LAG_FRAMES {
+                self.resync();
+            }
+        }
+
+        self.fps_frames += 1;
+        let elapsed = self.fps_start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        self.fps = f64::from(self.fps_frames) / elapsed.as_secs_f64();
+        self.fps_frames = 0;
+        self.fps_start = Instant::now();
+        Some(self.fps)
+    }
+
+    // Sleeps for one frame without counting it, e.g. while paused.
+    pub fn idle(&mut self) {
+        thread::sleep(Duration::from_secs_f64(frame_secs()));
+        self.resync();
+        self.fps_frames = 0;
+        self.fps_start = Instant::now();
+    }
+
+    // Frames per second measured over the last second.
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    pub fn drift(&self) -> Duration {
+        self.drift
+    }
+}
+
+fn frame_secs() -> f64 {
+    f64::from(CLOCKS_PER_FRAME) / f64::from(CLOCK_RATE)
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
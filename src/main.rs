@@ -1,30 +1,174 @@
 #![crate_name = "deepboy"]
 
-use deepboy::gameboy::Gameboy;
+use std::{fs, path::Path, process};
+
+use deepboy::{config::Config, cpu::CpuEvent, frontend::Frontend, gameboy::Gameboy, movie::Movie, script::Script, testrom};
+
+fn usage() -> ! {
+    eprintln!("usage: deepboy [--debug] [--load-state <state>] [--export-bess <state>] [--record <movie> | --play <movie>] <rom>");
+    eprintln!("       deepboy script <rom> <script>");
+    eprintln!("       deepboy test-rom [--timeout <frames>] <rom | dir>...");
+    process::exit(2);
+}
+
+// Runs an input script headless and exits non-zero if it fails.
+fn run_script(rom_name: &String, script_name: &String) -> ! {
+    let script = match Script::load(script_name) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("script: {}: {}", script_name, e);
+            process::exit(1);
+        }
+    };
+
+    let mut gameboy = Gameboy::headless(rom_name);
+    match script.run(&mut gameboy) {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            eprintln!("script: {}: {}", script_name, e);
+            process::exit(1);
+        }
+    }
+}
+
+// Runs Blargg and Mooneye test ROMs headless, prints a result table per argument
+// and exits non-zero unless all of them pass.
+fn run_test_roms(args: Vec<String>) -> ! {
+    let mut timeout = testrom::DEFAULT_TIMEOUT;
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                timeout = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage());
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut all_passed = true;
+    for path in paths.iter().map(Path::new) {
+        let (roms, base) = if path.is_dir() {
+            match testrom::find_roms(path) {
+                Ok(roms) => (roms, path),
+                Err(e) => {
+                    eprintln!("test-rom: {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            }
+        } else {
+            (vec![path.to_path_buf()], Path::new(""))
+        };
+
+        let results: Vec<_> = roms.iter().map(|rom| testrom::run(rom, timeout)).collect();
+        all_passed &= results.iter().all(|r| r.outcome == testrom::Outcome::Passed);
+        print!("{}", testrom::format_table(&results, base));
+    }
+    process::exit(if all_passed { 0 } else { 1 });
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let rom_name = &args[1];
+    let mut rom_name = None;
+    let mut record = None;
+    let mut play = None;
+    let mut load_state = None;
+    let mut export_bess = None;
+    let mut start_debugger = false;
+
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("script") {
+        let rest: Vec<String> = args.skip(1).collect();
+        match rest.as_slice() {
+            [rom_name, script_name] => run_script(rom_name, script_name),
+            _ => usage(),
+        }
+    }
+    if args.peek().map(String::as_str) == Some("test-rom") {
+        run_test_roms(args.skip(1).collect());
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = Some(args.next().unwrap_or_else(|| usage())),
+            "--play" => play = Some(args.next().unwrap_or_else(|| usage())),
+            "--load-state" => load_state = Some(args.next().unwrap_or_else(|| usage())),
+            "--export-bess" => export_bess = Some(args.next().unwrap_or_else(|| usage())),
+            "--debug" => start_debugger = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => rom_name = Some(arg),
+        }
+    }
+    let rom_name = rom_name.unwrap_or_else(|| usage());
     println!("rom: {rom}", rom=rom_name);
 
-    let mut gameboy = Gameboy::new(rom_name);
-    let debug = false;
-    // gameboy.cpu.set_debug();
-    // gameboy.mmc.borrow_mut().ppu.set_debug();
-    // gameboy.mmc.borrow_mut().timer.set_debug();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("config: {}", e);
+            process::exit(1);
+        }
+    };
 
-    let mut count: u32 = 0;
-    loop {
-        if debug {
-            println!("count:{}", count);
-            if count == 500000 {
-                panic!("For debug.");
-            }
-            count = count + 1;
+    let mut frontend = Frontend::new(&rom_name, &config);
+
+    let gameboy = &mut frontend.gameboy;
+    if let Some(fname) = &load_state {
+        if let Err(e) = gameboy.load_state_file(fname) {
+            eprintln!("state: {}: {}", fname, e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(fname) = &play {
+        let result = Movie::load(fname).and_then(|movie| gameboy.play_movie(movie));
+        if let Err(e) = result {
+            eprintln!("movie: {}", e);
+            process::exit(1);
+        }
+    } else if record.is_some() {
+        // A movie recorded after loading a state starts from that state.
+        if load_state.is_some() {
+            gameboy.start_recording_from_state();
+        } else {
+            gameboy.start_recording();
         }
-        
-        if !gameboy.exec_frame() {
+    }
+
+    if start_debugger {
+        frontend.request_debugger();
+    }
+
+    loop {
+        if !frontend.exec_frame() {
             break;
         }
+
+        if let Some(CpuEvent::IllegalOpcode { pc, opcode }) = frontend.gameboy.poll_event() {
+            eprintln!("CPU locked up: illegal opcode 0x{:02x} at 0x{:04x}", opcode, pc);
+        }
+        if frontend.gameboy.take_movie_finished() {
+            println!("movie: playback finished");
+        }
+    }
+
+    let gameboy = &mut frontend.gameboy;
+    if let Some(fname) = &export_bess {
+        match fs::write(fname, gameboy.export_bess()) {
+            Ok(()) => println!("state: exported {}", fname),
+            Err(e) => eprintln!("state: {}: {}", fname, e),
+        }
+    }
+
+    if let Some(fname) = &record {
+        if let Some(movie) = gameboy.stop_recording() {
+            match movie.save(fname) {
+                Ok(()) => println!("movie: recorded {} frames to {}", movie.frames.len(), fname),
+                Err(e) => eprintln!("movie: {}", e),
+            }
+        }
     }
 }
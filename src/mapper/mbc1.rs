@@ -1,26 +1,59 @@
+use std::sync::Arc;
 use crate::defs::BankMode;
+use crate::state::{StateError, StateReader, StateWriter};
 use super::Mapper;
 
+#[derive(Clone)]
 pub struct Mbc1 {
-    rom: Vec<u8>,
+    rom: Arc<[u8]>,
     ram: Vec<u8>,
     ram_enable: bool,
+    // Lower 5 bits of the ROM bank.
     rom_bank: u8,
+    // RAM bank, or bits 5-6 of the ROM bank.
     ram_bank: u8,
     bank_mode: BankMode,
 }
 
 impl Mbc1 {
-    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Self {
+    pub fn new(rom: Arc<[u8]>, ram: Vec<u8>) -> Self {
         Mbc1 {
-            rom: rom,
-            ram: ram,
+            rom,
+            ram,
             ram_enable: false,
             rom_bank: 0x01,
             ram_bank: 0,
             bank_mode: BankMode::Rom,
         }
     }
+
+    // ROM bank mapped at `addr`. RAM banking mode also maps `ram_bank << 5` at 0x0000.
+    fn rom_bank_at(&self, addr: u16) -> usize {
+        let bank = match (addr, self.bank_mode) {
+            (0x0000..=0x3FFF, BankMode::Rom) => 0,
+            (0x0000..=0x3FFF, BankMode::Ram) => (self.ram_bank as usize) << 5,
+            _ => ((self.ram_bank as usize) << 5) | self.rom_bank as usize,
+        };
+        // Bank numbers wrap around the ROM size.
+        bank % (self.rom.len() / 0x4000).max(1)
+    }
+
+    // ROM banking mode always maps RAM bank 0.
+    fn ram_bank_at(&self) -> usize {
+        match self.bank_mode {
+            BankMode::Rom => 0,
+            BankMode::Ram => self.ram_bank as usize % (self.ram.len() / 0x2000).max(1),
+        }
+    }
+
+    // Index into `ram` for `addr`, None without RAM. 2 KiB RAM is mirrored across the bank.
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = 0x2000 * self.ram_bank_at();
+        Some((addr as usize - 0xA000 + offset) % self.ram.len())
+    }
 }
 
 impl Mapper for Mbc1 {
@@ -28,26 +61,14 @@ impl Mapper for Mbc1 {
         // println!("MBC1 read addr:{:x}, ram_enable:{}", addr, self.ram_enable);
         match addr {
             0x0000..=0x3FFF => {
-                match self.bank_mode {
-                    BankMode::Rom => self.rom[addr as usize],
-                    BankMode::Ram => {
-                        let offset = 0x4000 * ((self.ram_bank << 5) + self.rom_bank) as usize;
-                        self.rom[addr as usize + offset]
-                    }
-                }
+                let offset = 0x4000 * self.rom_bank_at(addr);
+                self.rom[addr as usize + offset]
             }
             0x4000..=0x7FFF => {
-                let offset = 0x4000 * self.rom_bank as usize;
-                self.rom[(addr as usize - 0x4000 + offset)]
-            }
-            0xA000..=0xBFFF => {
-                if self.ram_enable {
-                    let offset = 0x2000 * self.ram_bank as usize;
-                    self.ram[addr as usize - 0xA000 + offset]
-                } else {
-                    0
-                }
+                let offset = 0x4000 * self.rom_bank_at(addr);
+                self.rom[addr as usize - 0x4000 + offset]
             }
+            0xA000..=0xBFFF if self.ram_enable => self.ram_addr(addr).map_or(0xFF, |i| self.ram[i]),
             _ => 0,
         }
     }
@@ -60,29 +81,95 @@ impl Mapper for Mbc1 {
                 if dat & 0xf == 0x0a { self.ram_enable = true }
             }
             0x2000..=0x3FFF => {
-                if dat == 0x00 { self.rom_bank = 0x01 }
-                if dat == 0x20 { self.rom_bank = 0x21; return }
-                if dat == 0x40 { self.rom_bank = 0x41; return }
-                if dat == 0x60 { self.rom_bank = 0x61; return }
-                self.rom_bank = dat & 0x1F;
+                // Bank 0 reads as bank 1, so 0x20, 0x40 and 0x60 map 0x21, 0x41 and 0x61.
+                self.rom_bank = match dat & 0x1F {
+                    0x00 => 0x01,
+                    bank => bank,
+                };
             }
             0x4000..=0x5FFF => {
                 self.ram_bank = dat & 0x03;
             }
             0x6000..=0x7FFF => {
-                match dat {
+                match dat & 0x01 {
                     0x00 => self.bank_mode = BankMode::Rom,
-                    0x01 => self.bank_mode = BankMode::Ram,
-                    n => panic!("Invalid bank_mode. {}", n),
+                    _ => self.bank_mode = BankMode::Ram,
                 };
             }
-            0xA000..=0xBFFF => {
-                if self.ram_enable {
-                    let offset = 0x2000 * self.ram_bank as usize;
-                    self.ram[addr as usize - 0xA000 + offset] = dat;
+            0xA000..=0xBFFF if self.ram_enable => {
+                if let Some(i) = self.ram_addr(addr) {
+                    self.ram[i] = dat;
                 }
             }
             _ => {},
         }
     }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enable);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_u8(match self.bank_mode {
+            BankMode::Rom => 0,
+            BankMode::Ram => 1,
+        });
+        w.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let ram_enable = r.read_bool()?;
+        let rom_bank = r.read_u8()?;
+        let ram_bank = r.read_u8()?;
+        let bank_mode = match r.read_u8()? {
+            0 => BankMode::Rom,
+            1 => BankMode::Ram,
+            _ => return Err(StateError::Format("invalid MBC1 bank mode")),
+        };
+
+        // Banks wrap around the ROM and RAM size, so only the register widths need checking.
+        if !(0x01..=0x1F).contains(&rom_bank) {
+            return Err(StateError::Format("MBC1 ROM bank out of range"));
+        }
+        if ram_bank > 0x03 {
+            return Err(StateError::Format("MBC1 RAM bank out of range"));
+        }
+        r.read_vec(&mut self.ram)?;
+
+        self.ram_enable = ram_enable;
+        self.rom_bank = rom_bank;
+        self.ram_bank = ram_bank;
+        self.bank_mode = bank_mode;
+        Ok(())
+    }
+
+    fn bank_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0A } else { 0x00 }),
+            (0x2000, self.rom_bank),
+            (0x4000, self.ram_bank),
+            (0x6000, match self.bank_mode {
+                BankMode::Rom => 0x00,
+                BankMode::Ram => 0x01,
+            }),
+        ]
+    }
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x7FFF => self.rom_bank_at(addr) as u16,
+            _ => self.ram_bank_at() as u16,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
 }
@@ -1,12 +1,145 @@
-// Not implemented yet
-pub struct Mbc3 {
+use std::sync::Arc;
+use crate::state::{StateError, StateReader, StateWriter};
+use super::Mapper;
 
+// The clock registers can be latched, read and written, but the clock doesn't tick yet.
+#[derive(Clone)]
+pub struct Mbc3 {
+    rom: Arc<[u8]>,
+    ram: Vec<u8>,
+    ram_enable: bool,
+    rom_bank: u8,
+    // 0x00-0x03 selects a RAM bank, 0x08-0x0C a clock register.
+    ram_bank: u8,
+    // Seconds, minutes, hours, day low, day high.
+    rtc: [u8; 5],
+    rtc_latched: [u8; 5],
+    latch: u8,
 }
 
 impl Mbc3 {
-    pub fn new() -> Self {
+    pub fn new(rom: Arc<[u8]>, ram: Vec<u8>) -> Self {
         Mbc3 {
+            rom,
+            ram,
+            ram_enable: false,
+            rom_bank: 0x01,
+            ram_bank: 0,
+            rtc: [0; 5],
+            rtc_latched: [0; 5],
+            latch: 0xFF,
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = 0x2000 * self.ram_bank as usize;
+        Some((addr as usize - 0xA000 + offset) % self.ram.len())
+    }
+}
 
+impl Mapper for Mbc3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => {
+                let offset = 0x4000 * self.rom_bank as usize;
+                self.rom[(addr as usize - 0x4000 + offset) % self.rom.len()]
+            }
+            0xA000..=0xBFFF if self.ram_enable => match self.ram_bank {
+                0x00..=0x03 => self.ram_addr(addr).map_or(0xFF, |i| self.ram[i]),
+                0x08..=0x0C => self.rtc_latched[(self.ram_bank - 0x08) as usize],
+                _ => 0xFF,
+            },
+            _ => 0xFF,
         }
     }
-}
\ No newline at end of file
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = dat & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = if dat & 0x7F == 0 { 0x01 } else { dat & 0x7F },
+            0x4000..=0x5FFF => self.ram_bank = dat,
+            0x6000..=0x7FFF => {
+                // Writing 0x00 then 0x01 copies the clock into the latched registers.
+                if self.latch == 0x00 && dat == 0x01 {
+                    self.rtc_latched = self.rtc;
+                }
+                self.latch = dat;
+            }
+            0xA000..=0xBFFF if self.ram_enable => match self.ram_bank {
+                0x00..=0x03 => {
+                    if let Some(i) = self.ram_addr(addr) {
+                        self.ram[i] = dat;
+                    }
+                }
+                0x08..=0x0C => {
+                    let i = (self.ram_bank - 0x08) as usize;
+                    self.rtc[i] = dat;
+                    self.rtc_latched[i] = dat;
+                }
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enable);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bytes(&self.rtc);
+        w.write_bytes(&self.rtc_latched);
+        w.write_u8(self.latch);
+        w.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = r.read_bool()?;
+        self.rom_bank = r.read_u8()?;
+        self.ram_bank = r.read_u8()?;
+        r.read_bytes(&mut self.rtc)?;
+        r.read_bytes(&mut self.rtc_latched)?;
+        self.latch = r.read_u8()?;
+        r.read_vec(&mut self.ram)
+    }
+
+    fn bank_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0A } else { 0x00 }),
+            (0x2000, self.rom_bank),
+            (0x4000, self.ram_bank),
+        ]
+    }
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => u16::from(self.rom_bank),
+            _ => u16::from(self.ram_bank),
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc(&self) -> Option<([u8; 5], [u8; 5])> {
+        Some((self.rtc, self.rtc_latched))
+    }
+
+    fn set_rtc(&mut self, current: [u8; 5], latched: [u8; 5]) {
+        self.rtc = current;
+        self.rtc_latched = latched;
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
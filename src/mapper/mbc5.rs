@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use crate::state::{StateError, StateReader, StateWriter};
+use super::Mapper;
+
+#[derive(Clone)]
+pub struct Mbc5 {
+    rom: Arc<[u8]>,
+    ram: Vec<u8>,
+    ram_enable: bool,
+    // 9 bits. Unlike MBC1 and MBC3, bank 0 can be mapped to 0x4000-0x7FFF.
+    rom_bank: u16,
+    ram_bank: u8,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Arc<[u8]>, ram: Vec<u8>) -> Self {
+        Mbc5 {
+            rom,
+            ram,
+            ram_enable: false,
+            rom_bank: 0x01,
+            ram_bank: 0,
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = 0x2000 * self.ram_bank as usize;
+        Some((addr as usize - 0xA000 + offset) % self.ram.len())
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => {
+                let offset = 0x4000 * self.rom_bank as usize;
+                self.rom[(addr as usize - 0x4000 + offset) % self.rom.len()]
+            }
+            0xA000..=0xBFFF if self.ram_enable => self.ram_addr(addr).map_or(0xFF, |i| self.ram[i]),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = dat == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | u16::from(dat),
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (u16::from(dat & 0x01) << 8),
+            0x4000..=0x5FFF => self.ram_bank = dat & 0x0F,
+            0xA000..=0xBFFF if self.ram_enable => {
+                if let Some(i) = self.ram_addr(addr) {
+                    self.ram[i] = dat;
+                }
+            }
+            _ => {},
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enable);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enable = r.read_bool()?;
+        self.rom_bank = r.read_u16()? & 0x1FF;
+        self.ram_bank = r.read_u8()? & 0x0F;
+        r.read_vec(&mut self.ram)
+    }
+
+    fn bank_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0A } else { 0x00 }),
+            (0x2000, self.rom_bank as u8),
+            (0x3000, (self.rom_bank >> 8) as u8),
+            (0x4000, self.ram_bank),
+        ]
+    }
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => self.rom_bank,
+            _ => u16::from(self.ram_bank),
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
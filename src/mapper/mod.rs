@@ -1,8 +1,37 @@
-pub trait Mapper {
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub trait Mapper: Send {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, dat: u8);
+    // Banking registers and cartridge RAM. ROM is not part of a save state.
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+    // Register writes that restore the current banking, used by BESS export.
+    fn bank_writes(&self) -> Vec<(u16, u8)>;
+    // Bank mapped at a ROM (0x0000-0x7FFF) or cartridge RAM (0xA000-0xBFFF) address.
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x4000..=0x7FFF => 1,
+            _ => 0,
+        }
+    }
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+    // MBC3 clock registers (S, M, H, DL, DH), current and latched.
+    fn rtc(&self) -> Option<([u8; 5], [u8; 5])> {
+        None
+    }
+    fn set_rtc(&mut self, _current: [u8; 5], _latched: [u8; 5]) {}
+    fn box_clone(&self) -> Box<dyn Mapper>;
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
 }
 
 pub mod nombc;
 pub mod mbc1;
-pub mod mbc3;
\ No newline at end of file
+pub mod mbc3;
+pub mod mbc5;
\ No newline at end of file
@@ -1,21 +1,46 @@
+use std::sync::Arc;
+use crate::state::{StateError, StateReader, StateWriter};
 use super::Mapper;
 
+#[derive(Clone)]
 pub struct NoMbc {
-    ram: Vec<u8>,
+    rom: Arc<[u8]>,
 }
 
 impl NoMbc {
-    pub fn new(ram: Vec<u8>) -> Self {
+    pub fn new(rom: Arc<[u8]>) -> Self {
         NoMbc {
-            ram: ram,
+            rom,
         }
     }
 }
 
 impl Mapper for NoMbc {
     fn read(&self, addr: u16) -> u8 {
-        self.ram[addr as usize]
+        self.rom[addr as usize]
     }
 
     fn write(&mut self, _: u16, _: u8) {}
+
+    fn save_state(&self, _: &mut StateWriter) {}
+
+    fn load_state(&mut self, _: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+
+    fn bank_writes(&self) -> Vec<(u16, u8)> {
+        Vec::new()
+    }
+
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
 }
\ No newline at end of file
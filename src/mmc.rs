@@ -1,38 +1,51 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use crate::joypad::Joypad;
+use crate::bus::Bus;
+use crate::debugger::{Access, WatchHit, Watchpoint};
+use crate::dma::Dma;
+use crate::joypad::{Button, Joypad};
 use crate::register::ByteRegister;
+use crate::serial::Serial;
+use crate::state::{StateError, StateReader, StateWriter};
 use crate::timer::Timer;
 
 use super::rom::Rom;
 use super::ppu::PPU;
 
+#[derive(Clone)]
 pub struct MMC {
     pub rom: Rom, 
     pub ppu: PPU,
     pub joypad: Joypad,
     pub timer: Timer,
+    pub dma: Dma,
+    pub serial: Serial,
     pub wram: [u8; 0x8000],
     pub bank: usize,
     pub hram: [u8; 0x7F],
+    pub key1: ByteRegister,
     pub int_enable: u8,
-    pub int_flag: Rc<RefCell<ByteRegister>>,
+    pub int_flag: ByteRegister,
+    // Only CPU accesses through `Bus` are watched, debugger peeks are not.
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hit: Option<WatchHit>,
 }
 
 impl MMC {
     pub fn new(fname: &String) -> Self {
-        let int_flag = Rc::new(RefCell::new(ByteRegister::new()));
         let mut m = MMC {
             rom: Rom::new(fname),
-            ppu: PPU::new(int_flag.clone()),
-            joypad: Joypad::new(int_flag.clone()),
-            timer: Timer::new(int_flag.clone()),
+            ppu: PPU::new(),
+            joypad: Joypad::new(),
+            timer: Timer::new(),
+            dma: Dma::new(),
+            serial: Serial::new(),
             wram: [0x00; 0x8000],
             bank: 0x01,
             hram: [0x00; 0x7F],
+            key1: ByteRegister::new(),
             int_enable: 0,
-            int_flag: int_flag.clone(),
+            int_flag: ByteRegister::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
         };
         m.write(0xff05, 0x00);
         m.write(0xff06, 0x00);
@@ -68,8 +81,28 @@ impl MMC {
         m
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        let result = match addr {
+    pub fn read(&self, addr: u16) -> u8 {
+        if self.dma.active() {
+            match addr {
+                0xFE00..=0xFE9F => return 0xFF,
+                0x0000..=0xFEFF => return self.dma.last_byte(),
+                _ => {},
+            }
+        }
+        self.bus_read(addr)
+    }
+
+    // Reads memory regardless of the PPU mode and OAM DMA, for debuggers and scripts.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.ppu.get_vram(addr),
+            0xFE00..=0xFE9F => self.ppu.oam()[addr as usize - 0xFE00],
+            _ => self.bus_read(addr),
+        }
+    }
+
+    fn bus_read(&self, addr: u16) -> u8 {
+        match addr {
             0x0000..=0x7FFF => self.rom.read(addr),
             0x8000..=0x9FFF => self.ppu.read(addr),
             0xA000..=0xBFFF => self.rom.read(addr),
@@ -79,21 +112,26 @@ impl MMC {
             0xF000..=0xFDFF => self.wram[(addr as usize) - 0xF000 + (0x1000 * self.bank)],
             0xFE00..=0xFE9F => self.ppu.read(addr),
             0xFF00 => self.joypad.read(addr),
+            0xFF01..=0xFF02 => self.serial.read(addr),
             0xFF04..=0xFF07 => self.timer.read(addr),
-            0xFF0F => self.int_flag.borrow_mut().data,
+            0xFF0F => self.int_flag.data,
             0xFF40..=0xFF45 => self.ppu.read(addr),
+            0xFF46 => self.dma.read(),
             0xFF47..=0xFF4B => self.ppu.read(addr),
+            0xFF4D => if self.rom.cgb { self.key1.get() | 0x7E } else { 0xFF },
             0xFF50 => self.rom.disable_boot_rom,
             0xFF80..=0xFFFE => self.hram[(addr as usize) - 0xFF80],
             0xFFFF => self.int_enable,
             _ => 0,
-        };
-
-        result
+        }
     }
 
     pub fn write(&mut self, addr: u16, dat: u8) {
         // println!("mmc write addr:0x{:x}, dat:0x{:x}", addr, dat);
+        if self.dma.active() && addr < 0xFF00 {
+            return;
+        }
+
         match addr {
             0x0000..=0x7FFF => self.rom.write(addr, dat),
             0x8000..=0x9FFF => self.ppu.write(addr, dat),
@@ -103,12 +141,14 @@ impl MMC {
             0xE000..=0xEFFF => self.wram[addr as usize - 0xE000] = dat,
             0xF000..=0xFDFF => self.wram[(addr as usize) - 0xF000 + (0x1000 * self.bank)] = dat,
             0xFE00..=0xFE9F => self.ppu.write(addr, dat),
-            0xFF00 => self.joypad.write(addr, dat),
+            0xFF00 => self.joypad.write(addr, dat, &mut self.int_flag),
+            0xFF01..=0xFF02 => self.serial.write(addr, dat),
             0xFF04..=0xFF07 => self.timer.write(addr, dat),
-            0xFF0F => self.int_flag.borrow_mut().data = dat,
+            0xFF0F => self.int_flag.data = dat,
             0xFF40..=0xFF45 => self.ppu.write(addr, dat),
-            0xFF46 => self.oam_dma_transfer(dat),
+            0xFF46 => self.dma.start(dat),
             0xFF47..=0xFF4B => self.ppu.write(addr, dat),
+            0xFF4D if self.rom.cgb => self.key1.set_bit(0, dat & 0x01 != 0),
             0xFF50 => self.rom.disable_boot_rom = dat,
             0xFF80..=0xFFFE => self.hram[(addr as usize) - 0xFF80] = dat,
             0xFFFF => self.int_enable = dat,
@@ -116,13 +156,172 @@ impl MMC {
         }
     }
 
-    pub fn oam_dma_transfer(&mut self, dat: u8) {
-        let start_addr = 0x100 * dat as u16;
+    // Keeps the first access matching a watchpoint until `watch_hit` is taken.
+    #[cold]
+    fn watch(&mut self, access: Access, addr: u16, old: u8, new: u8) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let bank = self.bank(addr);
+        if let Some(index) = self.watchpoints.iter().position(|w| w.matches(access, addr, bank, new)) {
+            self.watch_hit = Some(WatchHit { index, access, addr, bank, old, new });
+        }
+    }
 
-        for i in 0..=0x9F {
-            let oam_addr = 0xFE00 + i;
-            let oam_dat = self.read(start_addr + i);
-            self.ppu.write(oam_addr, oam_dat);
+    // Bank mapped at `addr`: the ROM or cartridge RAM bank, or the WRAM bank at 0xD000-0xDFFF.
+    // Unbanked areas are bank 0.
+    pub fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.rom.mapper.bank(addr),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => self.bank as u16,
+            _ => 0,
         }
     }
-}
\ No newline at end of file
+
+    // KEY1 bit 0 arms a CGB speed switch, which the next STOP performs.
+    pub fn speed_switch_armed(&self) -> bool {
+        self.key1.check_bit(0)
+    }
+
+    pub fn switch_speed(&mut self) {
+        let double_speed = self.double_speed();
+        self.key1.set_bit(7, !double_speed);
+        self.key1.set_bit(0, false);
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.rom.save_state(w);
+        self.ppu.save_state(w);
+        self.joypad.save_state(w);
+        self.timer.save_state(w);
+        self.dma.save_state(w);
+        self.serial.save_state(w);
+        w.write_bytes(&self.wram);
+        w.write_u8(self.bank as u8);
+        w.write_bytes(&self.hram);
+        w.write_u8(self.key1.get());
+        w.write_u8(self.int_enable);
+        w.write_u8(self.int_flag.get());
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.rom.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.dma.load_state(r)?;
+        self.serial.load_state(r)?;
+        r.read_bytes(&mut self.wram)?;
+        self.bank = match r.read_u8()? {
+            bank @ 0x01..=0x07 => bank as usize,
+            _ => return Err(StateError::Format("invalid WRAM bank")),
+        };
+        r.read_bytes(&mut self.hram)?;
+        self.key1.set(r.read_u8()?);
+        self.int_enable = r.read_u8()?;
+        self.int_flag.set(r.read_u8()?);
+        Ok(())
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.key1.check_bit(7)
+    }
+
+    // Advances the timer, serial port, OAM DMA and PPU, one M-cycle at a time from the CPU.
+    pub fn tick(&mut self, cycles: u32) {
+        self.timer.run(cycles, &mut self.int_flag);
+        self.tick_stopped(cycles);
+    }
+
+    // STOP holds the divider in reset, so everything but the timer advances.
+    pub fn tick_stopped(&mut self, cycles: u32) {
+        self.serial.run(cycles, &mut self.int_flag);
+        self.run_dma(cycles);
+        // The PPU keeps its pace when the CPU runs at double speed.
+        let ppu_cycles = if self.double_speed() { cycles / 2 } else { cycles };
+        self.ppu.run(ppu_cycles, &mut self.int_flag);
+    }
+
+    // Buttons are pressed through MMC so the joypad can raise its interrupt.
+    pub fn press(&mut self, button: Button) {
+        self.joypad.press(button, &mut self.int_flag);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        self.joypad.set_buttons(buttons, &mut self.int_flag);
+    }
+
+    pub fn set_joypad_state(&mut self, state: u8) {
+        self.joypad.set_state(state, &mut self.int_flag);
+    }
+
+    pub fn run_dma(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            if let Some((src, dst)) = self.dma.step() {
+                let dat = self.dma_read(src);
+                self.dma.set_last_byte(dat);
+                self.ppu.set_oam(dst, dat);
+            }
+        }
+    }
+
+    // DMA reads are not affected by the CPU side bus restrictions.
+    // Sources above 0xDFFF are mirrored into WRAM.
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.ppu.get_vram(addr),
+            0xE000..=0xFFFF => self.bus_read(addr - 0x2000),
+            _ => self.bus_read(addr),
+        }
+    }
+}
+
+impl Bus for MMC {
+    fn read(&mut self, addr: u16) -> u8 {
+        let dat = MMC::read(self, addr);
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Read, addr, dat, dat);
+        }
+        dat
+    }
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = MMC::read(self, addr);
+            self.watch(Access::Write, addr, old, dat);
+        }
+        MMC::write(self, addr, dat)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        MMC::tick(self, cycles)
+    }
+
+    fn tick_stopped(&mut self, cycles: u32) {
+        MMC::tick_stopped(self, cycles)
+    }
+
+    fn int_enable(&self) -> u8 {
+        self.int_enable
+    }
+
+    fn int_flag(&self) -> u8 {
+        self.int_flag.get()
+    }
+
+    fn set_int_flag(&mut self, dat: u8) {
+        self.int_flag.set(dat);
+    }
+
+    fn speed_switch_armed(&self) -> bool {
+        MMC::speed_switch_armed(self)
+    }
+
+    fn switch_speed(&mut self) {
+        MMC::switch_speed(self)
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt, fs, io, path::Path};
+use crate::state::StateError;
+
+const MAGIC: &[u8; 4] = b"DBMV";
+const VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Format(&'static str),
+    RomMismatch { expected: u32, found: u32 },
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "{}", e),
+            MovieError::Format(msg) => write!(f, "invalid movie file: {}", msg),
+            MovieError::RomMismatch { expected, found } => {
+                write!(f, "movie was recorded with ROM crc32 {:08x}, but this ROM is {:08x}", expected, found)
+            }
+            MovieError::State(e) => write!(f, "movie start state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieStart {
+    PowerOn,
+    // A save state, see `Gameboy::save_state`.
+    SaveState(Vec<u8>),
+}
+
+// Button state of every frame, see `Joypad::state`.
+//
+// File layout (little endian):
+//   "DBMV", version: u8, rom crc32: u32, start: u8, frame count: u32, frames: [u8]
+// followed by state size: u32, state: [u8] for movies starting from a save state.
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub start: MovieStart,
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(rom_crc32: u32, start: MovieStart) -> Self {
+        Movie {
+            rom_crc32,
+            start,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(14 + self.frames.len());
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        match self.start {
+            MovieStart::PowerOn => buf.push(0),
+            MovieStart::SaveState(_) => buf.push(1),
+        }
+        buf.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.frames);
+        if let MovieStart::SaveState(state) = &self.start {
+            buf.extend_from_slice(&(state.len() as u32).to_le_bytes());
+            buf.extend_from_slice(state);
+        }
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, MovieError> {
+        if buf.len() < 14 || &buf[0..4] != MAGIC {
+            return Err(MovieError::Format("bad header"));
+        }
+        if buf[4] != VERSION {
+            return Err(MovieError::Format("unsupported version"));
+        }
+
+        let rom_crc32 = u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]);
+        let len = u32::from_le_bytes([buf[10], buf[11], buf[12], buf[13]]) as usize;
+        let frames = buf.get(14..14 + len).ok_or(MovieError::Format("truncated frames"))?;
+
+        let start = match buf[9] {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let pos = 14 + len;
+                let size = buf.get(pos..pos + 4).ok_or(MovieError::Format("truncated state"))?;
+                let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+                let state = buf.get(pos + 4..pos + 4 + size).ok_or(MovieError::Format("truncated state"))?;
+                MovieStart::SaveState(state.to_vec())
+            }
+            _ => return Err(MovieError::Format("unknown start type")),
+        };
+
+        Ok(Movie {
+            rom_crc32,
+            start,
+            frames: frames.to_vec(),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
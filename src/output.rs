@@ -1,17 +1,25 @@
-use std::{cell::RefCell, rc::Rc};
-use crate::{defs::{GAMEBOY_WIDTH, GAMEBOY_HEIGHT, Color}, mmc::MMC};
+use crate::{config::{Config, Hotkey}, defs::{GAMEBOY_WIDTH, GAMEBOY_HEIGHT}, joypad::Button};
 
 pub struct Output {
     window: minifb::Window,
-    mmc: Rc<RefCell<MMC>>,
-    joypad_keys: Vec<minifb::Key>,
+    joypad_keys: Vec<(minifb::Key, Button)>,
+    turbo_keys: Vec<(minifb::Key, Button)>,
+    turbo_rate: u32,
+    // Turbo buttons held down and the frame they were pressed on.
+    turbo_held: Vec<(Button, u64)>,
+    frame: u64,
+    hotkeys: Vec<(minifb::Key, Hotkey)>,
+    pressed: Vec<Button>,
+    hotkeys_down: Vec<Hotkey>,
 }
 
 impl Output {
-    pub fn new(mmc: Rc<RefCell<MMC>>) -> Self {
-        let mut window_option = minifb::WindowOptions::default();
-        window_option.resize = true;
-        window_option.scale = minifb::Scale::X2;
+    pub fn new(config: &Config) -> Self {
+        let window_option = minifb::WindowOptions {
+            resize: true,
+            scale: minifb::Scale::X2,
+            ..minifb::WindowOptions::default()
+        };
         let mut window = minifb::Window::new(
             "deepboy",
             GAMEBOY_WIDTH,
@@ -22,70 +30,95 @@ impl Output {
         let buffer = vec![0; GAMEBOY_WIDTH * GAMEBOY_HEIGHT];
         window.update_with_buffer(buffer.as_slice(), GAMEBOY_WIDTH, GAMEBOY_HEIGHT).unwrap();
 
-        let joypad_keys: Vec<minifb::Key> = vec![
-            minifb::Key::Right,
-            minifb::Key::Left,
-            minifb::Key::Up,
-            minifb::Key::Down,
-            minifb::Key::A,
-            minifb::Key::B,
-            minifb::Key::Space,
-            minifb::Key::Enter,
-        ];
-
         Output {
-            window: window,
-            mmc: mmc,
-            joypad_keys: joypad_keys,
+            window,
+            joypad_keys: config.buttons.clone(),
+            turbo_keys: config.turbo_buttons.clone(),
+            turbo_rate: config.turbo_rate,
+            turbo_held: Vec::new(),
+            frame: 0,
+            hotkeys: config.hotkeys.clone(),
+            pressed: Vec::new(),
+            hotkeys_down: Vec::new(),
         }
     }
 
-    pub fn write_screen(&mut self) {
-        let mut color_buffer = [[[0x0; 3]; GAMEBOY_WIDTH as usize]; GAMEBOY_HEIGHT as usize];
-        for y in 0..GAMEBOY_HEIGHT {
-            for x in 0..GAMEBOY_WIDTH {
-                let color= self.convert_color(self.mmc.borrow_mut().ppu.frame_buffer[y][x][0]) as u8;
-                color_buffer[y][x] = [color, color, color];
-            }
-        }
+    // Draws an RGB24 frame, see `Gameboy::screen`.
+    pub fn write_screen(&mut self, rgb: &[u8]) {
+        let screen_buffer: Vec<u32> = rgb
+            .chunks(3)
+            .map(|p| (u32::from(p[0]) << 16) | (u32::from(p[1]) << 8) | u32::from(p[2]))
+            .collect();
 
-        self.mmc.borrow_mut().ppu.reset_buffer();
+        // screen_buffer = self.debug_screen_out(screen_buffer);
+        self.window.update_with_buffer(screen_buffer.as_slice(), GAMEBOY_WIDTH, GAMEBOY_HEIGHT).unwrap();
+        self.frame += 1;
+    }
+
+    // Processes window events without drawing a new frame, e.g. while paused.
+    pub fn update(&mut self) {
+        self.window.update();
+    }
 
-        let mut screen_buffer = vec![0; GAMEBOY_WIDTH * GAMEBOY_HEIGHT];
-        let mut i: usize = 0;
-        for buffer in color_buffer.iter() {
-            for rgb in buffer.iter() {
-                let r = (rgb[2] as u32) << 16;
-                let g = (rgb[1] as u32) << 8;
-                let b = rgb[0] as u32;
+    // Returns the buttons whose key changed state since the last call,
+    // so input coming from the Gameboy API is not overwritten every step.
+    pub fn handle_keys(&mut self) -> Vec<(Button, bool)> {
+        let mut pressed: Vec<Button> = self.joypad_keys
+            .iter()
+            .filter(|(key, _)| self.window.is_key_down(*key))
+            .map(|(_, button)| *button)
+            .collect();
 
-                screen_buffer[i] = r | g | b;
-                i += 1;
+        // Turbo buttons alternate between pressed and released every `turbo_rate` frames,
+        // starting pressed, so the game sees a real transition on each press.
+        let turbo: Vec<Button> = self.turbo_keys
+            .iter()
+            .filter(|(key, _)| self.window.is_key_down(*key))
+            .map(|(_, button)| *button)
+            .collect();
+        self.turbo_held.retain(|(button, _)| turbo.contains(button));
+        for button in turbo {
+            if !self.turbo_held.iter().any(|(b, _)| *b == button) {
+                self.turbo_held.push((button, self.frame));
+            }
+        }
+        for (button, since) in self.turbo_held.iter() {
+            let phase = (self.frame - since) / u64::from(self.turbo_rate);
+            if phase & 0x01 == 0 && !pressed.contains(button) {
+                pressed.push(*button);
             }
         }
 
-        // screen_buffer = self.debug_screen_out(screen_buffer);
-        self.window.update_with_buffer(screen_buffer.as_slice(), GAMEBOY_WIDTH, GAMEBOY_HEIGHT).unwrap();
+        let mut changes = Vec::new();
+        for button in Button::ALL.iter() {
+            let down = pressed.contains(button);
+            if down != self.pressed.contains(button) {
+                changes.push((*button, down));
+            }
+        }
+        self.pressed = pressed;
+        changes
     }
 
-    pub fn convert_color(&self, color: u8) -> Color {
-        match color {
-            0 => return Color::White,
-            1 => return Color::LightGray,
-            2 => return Color::DarkGray,
-            3 => return Color::Gray,
-            _ => panic!("Undefined color."),
-        };
+    // Returns the hotkeys pressed since the last call.
+    pub fn handle_hotkeys(&mut self) -> Vec<Hotkey> {
+        let down: Vec<Hotkey> = self.hotkeys
+            .iter()
+            .filter(|(key, _)| self.window.is_key_down(*key))
+            .map(|(_, hotkey)| *hotkey)
+            .collect();
+
+        let pressed = down.iter().filter(|h| !self.hotkeys_down.contains(h)).copied().collect();
+        self.hotkeys_down = down;
+        pressed
     }
 
-    pub fn handle_keys(&mut self) {
-        for key in &self.joypad_keys {
-            if self.window.is_key_down(*key) {
-                self.mmc.borrow_mut().joypad.key_down(*key);
-            } else if self.window.is_key_released(*key) {
-                self.mmc.borrow_mut().joypad.key_up(*key);
-            }
-        }
+    pub fn is_hotkey_down(&self, hotkey: Hotkey) -> bool {
+        self.hotkeys_down.contains(&hotkey)
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
     }
 
     pub fn window_is_open(&self) -> bool {
@@ -98,6 +131,6 @@ impl Output {
             println!("{:x}", v);
         }
 
-        return buf;
+        buf
     }
 }
\ No newline at end of file
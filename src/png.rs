@@ -0,0 +1,369 @@
+use std::{fs, io, path::Path};
+use crate::rom::crc32;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Minimal lossless PNG encoder for RGB24 images.
+// The image data is stored in uncompressed deflate blocks, which keeps it dependency free.
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bit depth, truecolor, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every scanline starts with filter type 0 (None).
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for line in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_store(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn write<P: AsRef<Path>>(path: P, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    fs::write(path, encode(width, height, rgb))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(if last { 0x01 } else { 0x00 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for d in data.iter() {
+        a = (a + u32::from(*d)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// Decodes a non-interlaced PNG with up to 8 bits per sample into (width, height, RGB24).
+// Alpha is dropped.
+pub fn decode(data: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(invalid("bad signature"));
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut idat = Vec::new();
+    let mut pos = SIGNATURE.len();
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("truncated chunk"))?;
+        match kind {
+            b"IHDR" if len == 13 => header = Some(body),
+            b"PLTE" => palette = body,
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {},
+        }
+        // Skip the CRC.
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or_else(|| invalid("missing IHDR"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(invalid("interlaced images are not supported"));
+    }
+    let channels = match (color_type, depth) {
+        (0, 1 | 2 | 4 | 8) | (3, 1 | 2 | 4 | 8) => 1,
+        (2, 8) => 3,
+        (4, 8) => 2,
+        (6, 8) => 4,
+        _ => return Err(invalid("unsupported color type or bit depth")),
+    };
+
+    let bits = channels * depth;
+    let stride = (width * bits).div_ceil(8);
+    let raw = inflate_zlib(&idat)?;
+    if raw.len() < (stride + 1) * height {
+        return Err(invalid("image data too short"));
+    }
+    let pixels = unfilter(&raw, stride, height, (bits / 8).max(1))?;
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for line in pixels.chunks(stride) {
+        for x in 0..width {
+            match color_type {
+                0 | 3 => {
+                    let bit = x * depth;
+                    let sample = (line[bit / 8] >> (8 - depth - bit % 8)) & ((1u16 << depth) - 1) as u8;
+                    if color_type == 0 {
+                        let v = (u32::from(sample) * 255 / ((1 << depth) - 1)) as u8;
+                        rgb.extend_from_slice(&[v, v, v]);
+                    } else {
+                        let i = sample as usize * 3;
+                        rgb.extend_from_slice(palette.get(i..i + 3).ok_or_else(|| invalid("palette index out of range"))?);
+                    }
+                }
+                4 => rgb.extend_from_slice(&[line[x * 2]; 3]),
+                _ => rgb.extend_from_slice(&line[x * channels..x * channels + 3]),
+            }
+        }
+    }
+    Ok((width, height, rgb))
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<(usize, usize, Vec<u8>)> {
+    decode(&fs::read(path)?)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("PNG: {}", msg))
+}
+
+// Reverses the per-scanline filters. `bpp` is the filter unit in bytes.
+fn unfilter(raw: &[u8], stride: usize, height: usize, bpp: usize) -> io::Result<Vec<u8>> {
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let src = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prev = if y > 0 { &done[(y - 1) * stride..] } else { &[][..] };
+        let cur = &mut rest[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { cur[i - bpp] } else { 0 };
+            let b = prev.get(i).copied().unwrap_or(0);
+            let c = if i >= bpp { prev.get(i - bpp).copied().unwrap_or(0) } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid("unknown filter type")),
+            };
+            cur[i] = src[i].wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+    1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order in which the code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    // Reads `n` bits, least significant first.
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("deflate data too short"))?;
+            value |= u32::from((byte >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// Canonical Huffman code: the number of codes per length and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+fn inflate_zlib(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0F != 8 || data[1] & 0x20 != 0 {
+        return Err(invalid("unsupported zlib stream"));
+    }
+    let mut r = BitReader { data: &data[2..], pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = r.bits(1)? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let header = r.data.get(r.pos..r.pos + 4).ok_or_else(|| invalid("deflate data too short"))?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                r.pos += 4;
+                out.extend_from_slice(r.data.get(r.pos..r.pos + len).ok_or_else(|| invalid("deflate data too short"))?);
+                r.pos += len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(&mut r, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut r)?;
+                inflate_block(&mut r, &mut out, &lit, &dist)?;
+            }
+            _ => return Err(invalid("bad deflate block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn dynamic_codes(r: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let hlit = r.bits(5)? as usize + 257;
+    let hdist = r.bits(5)? as usize + 1;
+    let hclen = r.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(hclen) {
+        code_lengths[i] = r.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (len, repeat) = match code_lengths.decode(r)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeat without a length"))?, 3 + r.bits(2)?),
+            17 => (0, 3 + r.bits(3)?),
+            _ => (0, 11 + r.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > hlit + hdist {
+        return Err(invalid("too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+    loop {
+        let symbol = lit.decode(r)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(invalid("bad length symbol"));
+                }
+                let len = LENGTH_BASE[i] as usize + r.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+                let d = dist.decode(r)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(invalid("bad distance symbol"));
+                }
+                let distance = DIST_BASE[d] as usize + r.bits(u32::from(DIST_EXTRA[d]))? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+                let start = out.len() - distance;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
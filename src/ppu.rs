@@ -1,12 +1,12 @@
-use std::{cell::RefCell, rc::Rc};
 use crate::defs::*;
 use crate::register::ByteRegister;
+use crate::state::{StateError, StateReader, StateWriter};
 
+#[derive(Clone)]
 pub struct PPU {
-    pub frame_buffer: [[[u8; 3]; GAMEBOY_WIDTH as usize]; GAMEBOY_HEIGHT as usize],
+    pub frame_buffer: [[[u8; 3]; GAMEBOY_WIDTH]; GAMEBOY_HEIGHT],
     vram: [u8; 0x4000],
     oamram: [u8; 0xa0],
-    int_flag: Rc<RefCell<ByteRegister>>,
     lcd_control: ByteRegister,
     lcd_status: ByteRegister,
     scroll_x: u8,
@@ -21,18 +21,18 @@ pub struct PPU {
     mode: VideoMode,
     pub v_blank: bool,
     cycles: u32,
+    access_restriction: bool,
     debug: bool,
 }
 
 impl PPU {
-    pub fn new(int_flag: Rc<RefCell<ByteRegister>>) -> Self {
+    pub fn new() -> Self {
         let mut sp1 = ByteRegister::new();
         sp1.set(1);
         PPU {
-            frame_buffer: [[[0x0; 3]; GAMEBOY_WIDTH as usize]; GAMEBOY_HEIGHT as usize],
+            frame_buffer: [[[0x0; 3]; GAMEBOY_WIDTH]; GAMEBOY_HEIGHT],
             vram: [0; 0x4000],
             oamram: [0; 0xa0],
-            int_flag: int_flag,
             lcd_control: ByteRegister::new(),
             lcd_status: ByteRegister::new(),
             scroll_x: 0,
@@ -47,21 +47,27 @@ impl PPU {
             mode: VideoMode::ACCESS_OAM,
             v_blank: false,
             cycles: 0,
+            access_restriction: true,
             debug: false,
         }
     }
 
-    pub fn set_debug(&mut self) {
-        self.debug = true;
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
     }
 
-    pub fn run(&mut self, cycles: u32) {
+    // Lets the CPU access VRAM and OAM in any mode. Useful for debugging homebrew.
+    pub fn disable_access_restriction(&mut self) {
+        self.access_restriction = false;
+    }
+
+    pub fn run(&mut self, cycles: u32, int_flag: &mut ByteRegister) {
         if self.debug { 
             println!("ppu next line:{}", self.line);
             println!("ppu next ly_compare:{}", self.ly_compare);
             println!("ppu next dots:{}", self.cycles);
             println!("ppu next mode:{}", self.mode as u8);
-            println!("ppu next intf:{:0>8b}", self.int_flag.borrow_mut().data);
+            println!("ppu next intf:{:0>8b}", int_flag.data);
             let bit = if self.line == self.ly_compare { 0x04 } else { 0x00 };
             println!("ppu next lcd_status:{:0>8b}", self.lcd_status.data | bit);
             println!("ppu next lcd_control:{:0>8b}", self.lcd_control.data);
@@ -92,7 +98,7 @@ impl PPU {
                     self.mode = VideoMode::HBLANK;
 
                     if self.lcd_status.check_bit(3) {
-                        self.int_flag.borrow_mut().set_bit(1, true);
+                        int_flag.set_bit(1, true);
                     }
 
                     self.lcd_status.set_bit(1, false);
@@ -111,14 +117,14 @@ impl PPU {
                     self.cycles %= CLOCKS_PER_SCANLINE;
                     let ly_coincidence = self.ly_compare == self.line;
                     if self.lcd_status.check_bit(6) && ly_coincidence {
-                        self.int_flag.borrow_mut().set_bit(1, true);
+                        int_flag.set_bit(1, true);
                     }
 
                     if self.line == 144 {
                         self.mode = VideoMode::VBLANK;
                         self.lcd_status.set_bit(1, false);
                         self.lcd_status.set_bit(0, true);
-                        self.int_flag.borrow_mut().set_bit(0, true); // ???
+                        int_flag.set_bit(0, true); // ???
                     } else {
                         self.lcd_status.set_bit(1, true);
                         self.lcd_status.set_bit(0, false);
@@ -151,13 +157,69 @@ impl PPU {
 
                     let ly_coincidence = self.ly_compare == self.line;
                     if self.lcd_status.check_bit(6) && ly_coincidence {
-                        self.int_flag.borrow_mut().set_bit(1, true);
+                        int_flag.set_bit(1, true);
                     }
                 }
             }
         }
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.vram);
+        w.write_bytes(&self.oamram);
+        w.write_u8(self.lcd_control.get());
+        w.write_u8(self.lcd_status.get());
+        w.write_u8(self.scroll_x);
+        w.write_u8(self.scroll_y);
+        w.write_u8(self.line);
+        w.write_u8(self.ly_compare);
+        w.write_u8(self.window_x);
+        w.write_u8(self.window_y);
+        w.write_u8(self.bg_palette.get());
+        w.write_u8(self.sprite_palette0.get());
+        w.write_u8(self.sprite_palette1.get());
+        w.write_u8(self.mode as u8);
+        w.write_bool(self.v_blank);
+        w.write_u32(self.cycles);
+        // The scanlines drawn so far in the current frame. All channels hold the same shade.
+        for pixel in self.frame_buffer.iter().flatten() {
+            w.write_u8(pixel[0]);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.vram)?;
+        r.read_bytes(&mut self.oamram)?;
+        self.lcd_control.set(r.read_u8()?);
+        self.lcd_status.set(r.read_u8()?);
+        self.scroll_x = r.read_u8()?;
+        self.scroll_y = r.read_u8()?;
+        self.line = r.read_u8()?;
+        self.ly_compare = r.read_u8()?;
+        self.window_x = r.read_u8()?;
+        self.window_y = r.read_u8()?;
+        self.bg_palette.set(r.read_u8()?);
+        self.sprite_palette0.set(r.read_u8()?);
+        self.sprite_palette1.set(r.read_u8()?);
+        self.mode = match r.read_u8()? {
+            0 => VideoMode::HBLANK,
+            1 => VideoMode::VBLANK,
+            2 => VideoMode::ACCESS_OAM,
+            3 => VideoMode::ACCESS_VRAM,
+            _ => return Err(StateError::Format("invalid PPU mode")),
+        };
+        self.v_blank = r.read_bool()?;
+        self.cycles = r.read_u32()?;
+        for pixel in self.frame_buffer.iter_mut().flatten() {
+            let shade = r.read_u8()?;
+            if shade > 3 {
+                return Err(StateError::Format("invalid pixel"));
+            }
+            *pixel = [shade; 3];
+        }
+        Ok(())
+    }
+
     pub fn lcd_enabled(&self) -> bool { self.lcd_control.check_bit(7) }
     pub fn window_tile_map(&self) -> bool { self.lcd_control.check_bit(6) }
     pub fn window_enabled(&self) -> bool { self.lcd_control.check_bit(5) }
@@ -167,27 +229,52 @@ impl PPU {
     pub fn sprite_enabled(&self) -> bool { self.lcd_control.check_bit(1) }
     pub fn bg_enabled(&self) -> bool { self.lcd_control.check_bit(0) }
 
+    // VRAM is locked while the PPU is drawing (mode 3).
+    pub fn vram_accessible(&self) -> bool {
+        if !self.access_restriction || !self.lcd_enabled() {
+            return true;
+        }
+        !matches!(self.mode, VideoMode::ACCESS_VRAM)
+    }
+
+    // OAM is locked during the OAM scan and while drawing (modes 2 and 3).
+    pub fn oam_accessible(&self) -> bool {
+        if !self.access_restriction || !self.lcd_enabled() {
+            return true;
+        }
+        !matches!(self.mode, VideoMode::ACCESS_OAM | VideoMode::ACCESS_VRAM)
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
-        let mut result: u8 = 0;
-        match addr {
-            0x8000..=0x9FFF => result = self.vram[addr as usize - 0x8000],
-            0xFE00..=0xFE9F => result = self.oamram[addr as usize - 0xFE00],
-            0xFF40 => result = self.lcd_control.get(),
+        let result = match addr {
+            0x8000..=0x9FFF => {
+                if !self.vram_accessible() {
+                    return 0xFF;
+                }
+                self.vram[addr as usize - 0x8000]
+            }
+            0xFE00..=0xFE9F => {
+                if !self.oam_accessible() {
+                    return 0xFF;
+                }
+                self.oamram[addr as usize - 0xFE00]
+            }
+            0xFF40 => self.lcd_control.get(),
             0xFF41 => {
                 let bit = if self.line == self.ly_compare { 0x04 } else { 0x00 };
-                result = self.lcd_status.get() | bit;
+                self.lcd_status.get() | bit
             }
-            0xFF42 => result = self.scroll_y,
-            0xFF43 => result = self.scroll_x,
-            0xFF44 => result = self.line,
-            0xFF45 => result = self.ly_compare,
-            0xFF47 => result = self.bg_palette.get(),
-            0xFF48 => result = self.sprite_palette0.get(),
-            0xFF49 => result = self.sprite_palette1.get(),
-            0xFF4A => result = self.window_y,
-            0xFF4B => result = self.window_x,
+            0xFF42 => self.scroll_y,
+            0xFF43 => self.scroll_x,
+            0xFF44 => self.line,
+            0xFF45 => self.ly_compare,
+            0xFF47 => self.bg_palette.get(),
+            0xFF48 => self.sprite_palette0.get(),
+            0xFF49 => self.sprite_palette1.get(),
+            0xFF4A => self.window_y,
+            0xFF4B => self.window_x,
             _ => panic!("PPU: Unknown address."),
-        }
+        };
 
         if self.debug {
             println!("ppu.read {:x} {:x}", addr, result);
@@ -202,8 +289,16 @@ impl PPU {
         }
 
         match addr {
-            0x8000..=0x9FFF => self.vram[addr as usize - 0x8000] = dat,
-            0xFE00..=0xFE9F => self.oamram[addr as usize - 0xFE00] = dat,
+            0x8000..=0x9FFF => {
+                if self.vram_accessible() {
+                    self.vram[addr as usize - 0x8000] = dat;
+                }
+            }
+            0xFE00..=0xFE9F => {
+                if self.oam_accessible() {
+                    self.oamram[addr as usize - 0xFE00] = dat;
+                }
+            }
             0xFF40 => {
                 self.lcd_control.set(dat);
                 if !self.lcd_enabled() {
@@ -244,13 +339,55 @@ impl PPU {
     }
 
     pub fn reset_buffer(&mut self) {
-        self.frame_buffer = [[[0x0; 3]; GAMEBOY_WIDTH as usize]; GAMEBOY_HEIGHT as usize];
+        self.frame_buffer = [[[0x0; 3]; GAMEBOY_WIDTH]; GAMEBOY_HEIGHT];
     }
 
     pub fn get_vram(&self, addr: u16) -> u8 {
         self.vram[addr as usize - 0x8000]
     }
 
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn vram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oamram
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.oamram
+    }
+
+    // Sets a register without the side effects of a CPU write, e.g. when importing a state.
+    // LY and the mode bits of STAT also move the PPU to the start of that line and mode.
+    pub fn set_register(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0xFF40 => self.lcd_control.set(dat),
+            0xFF41 => {
+                self.lcd_status.set(dat & 0x7B);
+                let (mode, cycles) = match dat & 0x03 {
+                    0 => (VideoMode::HBLANK, CLOCKS_PER_SCANLINE_OAM + CLOCKS_PER_SCANLINE_VRAM),
+                    1 => (VideoMode::VBLANK, 0),
+                    2 => (VideoMode::ACCESS_OAM, 0),
+                    _ => (VideoMode::ACCESS_VRAM, CLOCKS_PER_SCANLINE_OAM),
+                };
+                self.mode = mode;
+                self.cycles = cycles;
+            }
+            0xFF44 => self.line = dat.min(153),
+            _ => self.write(addr, dat),
+        }
+    }
+
+    // OAM DMA writes bypass the CPU access restriction.
+    pub fn set_oam(&mut self, addr: u16, dat: u8) {
+        self.oamram[addr as usize - 0xFE00] = dat;
+    }
+
     pub fn render_scanline(&mut self) {
         if self.debug {
             println!("render_scanline bg_enabled:{} window_enabled:{}", self.bg_enabled(), self.window_enabled());
@@ -309,7 +446,7 @@ impl PPU {
             let tile_pixel_y = bg_map_y % TILE_HEIGHT;
 
             let tile_index = tile_y  * TILES_PER_LINE + tile_x;
-            let tile_id_addr: u16 = tile_map_addr + tile_index as u16;
+            let tile_id_addr: u16 = tile_map_addr + tile_index;
 
             let tile_id = self.get_vram(tile_id_addr);
 
@@ -320,13 +457,13 @@ impl PPU {
             } as u16
               * 16;
 
-            let tile_line_offset = u16::from(tile_pixel_y) * 2;
+            let tile_line_offset = tile_pixel_y * 2;
             let tile_line_addr = tile_set_addr + tile_offset + tile_line_offset;
 
             let pixel1 = self.get_vram(tile_line_addr);
             let pixel2 = self.get_vram(tile_line_addr + 1);
 
-            let pixel_color = (((pixel2 >> (7 - tile_pixel_x)) & 1) << 1) | ((pixel1 >> (7 - tile_pixel_x) & 1));
+            let pixel_color = (((pixel2 >> (7 - tile_pixel_x)) & 1) << 1) | (pixel1 >> (7 - tile_pixel_x) & 1);
             let real_color = palette[pixel_color as usize];
 
             self.frame_buffer[screen_y as usize][screen_x as usize] = [real_color, real_color, real_color];
@@ -381,7 +518,7 @@ impl PPU {
             let tile_pixel_y = scrolled_y % TILE_HEIGHT;
 
             let tile_index = tile_y  * TILES_PER_LINE + tile_x;
-            let tile_id_addr: u16 = tile_map_addr + tile_index as u16;
+            let tile_id_addr: u16 = tile_map_addr + tile_index;
 
             let tile_id = self.get_vram(tile_id_addr);
 
@@ -392,13 +529,13 @@ impl PPU {
             } as u16
               * 16;
 
-            let tile_line_offset = u16::from(tile_pixel_y) * 2;
+            let tile_line_offset = tile_pixel_y * 2;
             let tile_line_addr = tile_set_addr + tile_offset + tile_line_offset;
 
             let pixel1 = self.get_vram(tile_line_addr);
             let pixel2 = self.get_vram(tile_line_addr + 1);
 
-            let pixel_color = (((pixel2 >> (7 - tile_pixel_x)) & 1) << 1) | ((pixel1 >> (7 - tile_pixel_x) & 1));
+            let pixel_color = (((pixel2 >> (7 - tile_pixel_x)) & 1) << 1) | (pixel1 >> (7 - tile_pixel_x) & 1);
             let real_color = palette[pixel_color as usize];
 
             self.frame_buffer[screen_y as usize][screen_x as usize] = [real_color, real_color, real_color];
@@ -476,7 +613,7 @@ impl PPU {
 
             let mut pixel_line: Vec<u8> = Vec::new();
             (0..8).for_each(|i: u8|{
-                let pixel_color = (((pixel2 >> (7 - i)) & 1) << 1) | ((pixel1 >> (7 - i) & 1));
+                let pixel_color = (((pixel2 >> (7 - i)) & 1) << 1) | (pixel1 >> (7 - i) & 1);
                 pixel_line.push(pixel_color);
             });
 
@@ -517,10 +654,8 @@ impl PPU {
                     continue;
                 }
 
-                if behind_bg {
-                    if self.frame_buffer[screen_y as usize][screen_x as usize] != white {
-                        continue;
-                    }
+                if behind_bg && self.frame_buffer[screen_y as usize][screen_x as usize] != white {
+                    continue;
                 }
 
                 let real_color = palette[pixel_color as usize];
@@ -542,7 +677,7 @@ impl PPU {
         let color2 = palette_reg.get_bit(5) << 1 | palette_reg.get_bit(4); 
         let color3 = palette_reg.get_bit(7) << 1 | palette_reg.get_bit(6); 
 
-        return [color0, color1, color2, color3];
+        [color0, color1, color2, color3]
     }
 
     pub fn debug_oam_out(&self) {
@@ -550,7 +685,7 @@ impl PPU {
         for v in self.oamram.iter() {
             print!("{:x}", v);
         }
-        println!("");
+        println!();
     }
 
     pub fn debug_tile_out(&self, tile: &[u8]) {
@@ -558,7 +693,7 @@ impl PPU {
         for v in tile.iter() {
             print!("{:x}", v);
         }
-        println!("");
+        println!();
     }
     
     pub fn debug_frame_out(&self, s: &str) {
@@ -569,4 +704,10 @@ impl PPU {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -35,6 +35,13 @@ impl ByteRegister {
     }
 }
 
+impl Default for ByteRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
 pub struct Register {
     pub a: u8,
     pub f: u8,
@@ -64,6 +71,7 @@ pub enum IoRegs {
     LYC = 0xFF45,
     WY = 0xFF4A,
     WX = 0xFF4B,
+    KEY1 = 0xFF4D,
     DMA = 0xFF46,
     BGP = 0xFF47,
     OBP0 = 0xFF48,
@@ -198,4 +206,10 @@ impl Register {
           self.f &= 0b11100000;
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for Register {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+// An older snapshot, stored as the compressed XOR against the next newer one.
+#[derive(Clone)]
+struct Snapshot {
+    delta: Vec<u8>,
+    // Input of every frame run after the snapshot, see `Joypad::state`.
+    inputs: Vec<u8>,
+}
+
+// History of save states taken every `interval` frames, bounded by `budget` bytes.
+// Only the newest snapshot is kept in full. Frames between snapshots are reached
+// by loading the snapshot before them and replaying the recorded inputs.
+#[derive(Clone)]
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    history: VecDeque<Snapshot>,
+    newest: Vec<u8>,
+    newest_inputs: Vec<u8>,
+    used: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        assert!(interval > 0, "Rewind: interval must be positive.");
+        Rewind {
+            interval,
+            budget,
+            history: VecDeque::new(),
+            newest: Vec::new(),
+            newest_inputs: Vec::new(),
+            used: 0,
+        }
+    }
+
+    // A snapshot should be pushed before the next frame.
+    pub fn snapshot_due(&self) -> bool {
+        self.newest.is_empty() || self.newest_inputs.len() >= self.interval as usize
+    }
+
+    pub fn push_snapshot(&mut self, state: Vec<u8>) {
+        if !self.newest.is_empty() {
+            let delta = compress_delta(&self.newest, &state);
+            self.used += delta.len() + self.newest_inputs.len();
+            self.history.push_back(Snapshot {
+                delta,
+                inputs: std::mem::take(&mut self.newest_inputs),
+            });
+        }
+        self.newest = state;
+        self.newest_inputs.clear();
+
+        while self.used + self.newest.len() > self.budget {
+            match self.history.pop_front() {
+                Some(oldest) => self.used -= oldest.delta.len() + oldest.inputs.len(),
+                None => break,
+            }
+        }
+    }
+
+    pub fn push_input(&mut self, input: u8) {
+        if !self.newest.is_empty() {
+            self.newest_inputs.push(input);
+        }
+    }
+
+    // Steps one frame back. Returns the snapshot to load and the inputs to replay on top of it,
+    // or None when the history is exhausted.
+    pub fn step_back(&mut self) -> Option<(&[u8], &[u8])> {
+        if self.newest_inputs.is_empty() {
+            let snapshot = self.history.pop_back()?;
+            self.used -= snapshot.delta.len() + snapshot.inputs.len();
+            apply_delta(&mut self.newest, &snapshot.delta);
+            self.newest_inputs = snapshot.inputs;
+            if self.newest_inputs.is_empty() {
+                return None;
+            }
+        }
+        self.newest_inputs.pop();
+        Some((&self.newest, &self.newest_inputs))
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.newest.clear();
+        self.newest_inputs.clear();
+        self.used = 0;
+    }
+
+    // Frames that can currently be rewound.
+    pub fn frames(&self) -> usize {
+        self.newest_inputs.len() + self.history.iter().map(|s| s.inputs.len()).sum::<usize>()
+    }
+
+    // Memory used by the history in bytes.
+    pub fn used(&self) -> usize {
+        self.used + self.newest.len()
+    }
+}
+
+// Run-length encodes `prev XOR cur` as (zero run, literal length, literal bytes) records,
+// each length a LEB128 varint. Consecutive states differ in few bytes, so runs dominate.
+fn compress_delta(prev: &[u8], cur: &[u8]) -> Vec<u8> {
+    assert_eq!(prev.len(), cur.len(), "Rewind: state size changed.");
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < cur.len() {
+        let start = i;
+        while i < cur.len() && prev[i] == cur[i] {
+            i += 1;
+        }
+        let zeros = i - start;
+
+        let start = i;
+        while i < cur.len() && prev[i] != cur[i] {
+            i += 1;
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, i - start);
+        out.extend(prev[start..i].iter().zip(&cur[start..i]).map(|(a, b)| a ^ b));
+    }
+    out
+}
+
+// XORs a delta into `state`. Applying the same delta twice restores the original.
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let len = read_varint(delta, &mut pos);
+        for (dst, x) in state[i..i + len].iter_mut().zip(&delta[pos..pos + len]) {
+            *dst ^= x;
+        }
+        i += len;
+        pos += len;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let b = buf[*pos];
+        *pos += 1;
+        n |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return n;
+        }
+        shift += 7;
+    }
+}
@@ -1,8 +1,12 @@
 use std::fs::File;
 use std::io::prelude::*;
+use std::sync::Arc;
 
 use crate::mapper::mbc1::Mbc1;
+use crate::mapper::mbc3::Mbc3;
+use crate::mapper::mbc5::Mbc5;
 use crate::mapper::nombc::NoMbc;
+use crate::state::{StateError, StateReader, StateWriter};
 use super::mapper::Mapper;
 
 pub const DMG: [u8; 0x100] = [
@@ -24,7 +28,23 @@ pub const DMG: [u8; 0x100] = [
     0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x00, 0x00, 0x3E, 0x01, 0xE0, 0x50
 ];
 
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+    for b in data.iter() {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            let mask = (!(crc & 0x01)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[derive(Clone)]
 pub struct Rom {
+    pub crc32: u32,
+    // CGB flag in the header, which enables CGB-only registers such as KEY1.
+    pub cgb: bool,
     pub mbc_type: u8,
     pub rom_size_type: u8,
     pub ram_size_type: u8,
@@ -39,6 +59,8 @@ impl Rom {
         let mut rom = Vec::new();
         f.read_to_end(&mut rom).unwrap();
 
+        let crc32 = crc32(&rom);
+        let cgb = rom[0x143] & 0x80 != 0;
         let mbc_type = rom[0x147];
         let rom_size_type = rom[0x148];
         let ram_size_type = rom[0x149];
@@ -54,9 +76,13 @@ impl Rom {
             1 => ram_size = 0x800,
             2 => ram_size = 0x2000,
             3 => ram_size = 0x8000,
+            4 => ram_size = 0x20000,
+            5 => ram_size = 0x10000,
             _ => println!("Invalid RAM SIZE TYPE")
         }
 
+        // Shared between clones of the machine, only the mapper state is copied.
+        let rom: Arc<[u8]> = rom.into();
         let mapper: Box<dyn Mapper> = match mbc_type {
             0 => {
                 println!("NoMBC");
@@ -66,23 +92,43 @@ impl Rom {
                 println!("MBC1");
                 Box::new(Mbc1::new(rom, vec![]))
             },
-            3 => {
+            2 | 3 => {
                 println!("MBC1");
                 Box::new(Mbc1::new(rom, vec![0; ram_size]))
             }
+            0x0F..=0x13 => {
+                println!("MBC3");
+                Box::new(Mbc3::new(rom, vec![0; ram_size]))
+            }
+            0x19..=0x1E => {
+                println!("MBC5");
+                Box::new(Mbc5::new(rom, vec![0; ram_size]))
+            }
             _n => panic!("Invalid mapper."),
         };
 
         Rom {
-            mbc_type: mbc_type,
-            rom_size_type: rom_size_type,
-            ram_size_type: ram_size_type,
-            ram_size: ram_size,
+            crc32,
+            cgb,
+            mbc_type,
+            rom_size_type,
+            ram_size_type,
+            ram_size,
             disable_boot_rom: 0,
-            mapper: mapper,
+            mapper,
         }
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.disable_boot_rom);
+        self.mapper.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.disable_boot_rom = r.read_u8()?;
+        self.mapper.load_state(r)
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         let uaddr: usize = addr as usize;
         match uaddr {
@@ -98,10 +144,8 @@ impl Rom {
     }
 
     pub fn write(&mut self, addr: u16, dat: u8) {
-        let uaddr: usize = addr as usize;
-        match uaddr {
-            0x0000..=0xBFFF => self.mapper.write(addr, dat),
-            _ => {},
+        if let 0x0000..=0xBFFF = addr {
+            self.mapper.write(addr, dat);
         }
     }
 }
\ No newline at end of file
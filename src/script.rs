@@ -0,0 +1,214 @@
+use std::{fmt, fs, io, path::Path};
+use crate::gameboy::Gameboy;
+use crate::joypad::Button;
+
+// Input script for headless runs. One command per line, `#` starts a comment.
+//
+//   wait 120                   run 120 frames
+//   press start 2              press start for 2 frames (default 1), then release it
+//   hold right 30              hold right for 30 frames, or until `release right` without a count
+//   release right
+//   screenshot title.png       write the last frame as PNG
+//   assert mem 0xC0A0 == 3     compare a byte of memory (==, !=, <, <=, >, >=)
+//   savestate after_intro      write a save state to `after_intro.state`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Wait(u32),
+    Press(Button, u32),
+    Hold(Button, Option<u32>),
+    Release(Button),
+    Screenshot(String),
+    AssertMem { addr: u16, op: CmpOp, value: u8 },
+    SaveState(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn parse(s: &str) -> Option<CmpOp> {
+        let op = match s {
+            "==" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            ">=" => CmpOp::Ge,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn eval(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse { line: usize, msg: String },
+    Assert { line: usize, addr: u16, op: CmpOp, expected: u8, found: u8 },
+    Command { line: usize, msg: String },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Io(e) => write!(f, "{}", e),
+            ScriptError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            ScriptError::Assert { line, addr, op, expected, found } => write!(
+                f,
+                "line {}: assertion failed: mem 0x{:04x} {} 0x{:02x} (found 0x{:02x})",
+                line, addr, op, expected, found
+            ),
+            ScriptError::Command { line, msg } => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self {
+        ScriptError::Io(e)
+    }
+}
+
+pub struct Script {
+    // Commands with their line numbers.
+    pub commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        Script::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut commands = Vec::new();
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let text = raw.split('#').next().unwrap_or("").trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            let words: Vec<&str> = text.split_whitespace().collect();
+            let command = parse_command(&words).map_err(|msg| ScriptError::Parse { line, msg })?;
+            commands.push((line, command));
+        }
+        Ok(Script { commands })
+    }
+
+    pub fn run(&self, gameboy: &mut Gameboy) -> Result<(), ScriptError> {
+        for (line, command) in self.commands.iter() {
+            let line = *line;
+            match command {
+                Command::Wait(frames) => run_frames(gameboy, *frames),
+                Command::Press(button, frames) => {
+                    gameboy.press(*button);
+                    run_frames(gameboy, *frames);
+                    gameboy.release(*button);
+                }
+                Command::Hold(button, frames) => {
+                    gameboy.press(*button);
+                    if let Some(frames) = frames {
+                        run_frames(gameboy, *frames);
+                        gameboy.release(*button);
+                    }
+                }
+                Command::Release(button) => gameboy.release(*button),
+                Command::Screenshot(fname) => {
+                    gameboy.save_screenshot(fname)
+                        .map_err(|e| ScriptError::Command { line, msg: format!("screenshot {}: {}", fname, e) })?;
+                }
+                Command::AssertMem { addr, op, value } => {
+                    let found = gameboy.read_memory(*addr);
+                    if !op.eval(found, *value) {
+                        return Err(ScriptError::Assert { line, addr: *addr, op: *op, expected: *value, found });
+                    }
+                }
+                Command::SaveState(name) => {
+                    let fname = format!("{}.state", name);
+                    fs::write(&fname, gameboy.save_state())
+                        .map_err(|e| ScriptError::Command { line, msg: format!("savestate {}: {}", fname, e) })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_frames(gameboy: &mut Gameboy, frames: u32) {
+    for _ in 0..frames {
+        gameboy.exec_frame();
+    }
+}
+
+fn parse_command(words: &[&str]) -> Result<Command, String> {
+    let command = match words {
+        ["wait", frames] => Command::Wait(parse_number(frames)?),
+        ["press", button] => Command::Press(parse_button(button)?, 1),
+        ["press", button, frames] => Command::Press(parse_button(button)?, parse_number(frames)?),
+        ["hold", button] => Command::Hold(parse_button(button)?, None),
+        ["hold", button, frames] => Command::Hold(parse_button(button)?, Some(parse_number(frames)?)),
+        ["release", button] => Command::Release(parse_button(button)?),
+        ["screenshot", fname] => Command::Screenshot(fname.to_string()),
+        ["assert", "mem", addr, op, value] => {
+            let addr = parse_number(addr)?;
+            let value = parse_number(value)?;
+            if addr > 0xFFFF {
+                return Err(format!("address out of range `{}`", addr));
+            }
+            if value > 0xFF {
+                return Err(format!("value out of range `{}`", value));
+            }
+            let op = CmpOp::parse(op).ok_or_else(|| format!("unknown operator `{}`", op))?;
+            Command::AssertMem { addr: addr as u16, op, value: value as u8 }
+        }
+        ["savestate", name] => Command::SaveState(name.to_string()),
+        _ => return Err(format!("unknown command `{}`", words.join(" "))),
+    };
+    Ok(command)
+}
+
+fn parse_button(s: &str) -> Result<Button, String> {
+    Button::from_name(s).ok_or_else(|| format!("unknown button `{}`", s))
+}
+
+fn parse_number(s: &str) -> Result<u32, String> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    result.map_err(|_| format!("invalid number `{}`", s))
+}
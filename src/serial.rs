@@ -0,0 +1,95 @@
+use crate::register::ByteRegister;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// T-cycles per transferred bit with the internal 8192 Hz clock.
+const CYCLES_PER_BIT: u32 = 512;
+
+// Serial port without a link partner. Transfers on the internal clock shift in 0xFF,
+// transfers on the external clock never complete.
+#[derive(Clone)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // T-cycles left in the running transfer.
+    remaining: u32,
+    // Every byte sent while capturing, e.g. the result text of test ROMs.
+    // None unless enabled, so normal runs don't keep a growing log.
+    pub output: Option<Vec<u8>>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            remaining: 0,
+            output: None,
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.sb);
+        w.write_u8(self.sc);
+        w.write_u32(self.remaining);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.sb = r.read_u8()?;
+        self.sc = r.read_u8()?;
+        self.remaining = r.read_u32()?;
+        Ok(())
+    }
+
+    // Sets a register without starting a transfer, e.g. when importing a state.
+    pub fn set_register(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0xFF01 => self.sb = dat,
+            0xFF02 => self.sc = dat & 0x81,
+            _ => panic!("Serial: Unknown address."),
+        }
+        self.remaining = 0;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc | 0x7E,
+            _ => panic!("Serial: Unknown address."),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0xFF01 => self.sb = dat,
+            0xFF02 => {
+                self.sc = dat & 0x81;
+                if self.sc == 0x81 {
+                    if let Some(output) = &mut self.output {
+                        output.push(self.sb);
+                    }
+                    self.remaining = CYCLES_PER_BIT * 8;
+                }
+            }
+            _ => panic!("Serial: Unknown address."),
+        }
+    }
+
+    pub fn run(&mut self, cycles: u32, int_flag: &mut ByteRegister) {
+        if self.remaining == 0 {
+            return;
+        }
+
+        self.remaining = self.remaining.saturating_sub(cycles);
+        if self.remaining == 0 {
+            self.sb = 0xFF;
+            self.sc &= 0x7F;
+            int_flag.set_bit(3, true);
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
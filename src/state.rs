@@ -0,0 +1,174 @@
+use std::{fmt, io};
+
+pub const MAGIC: &[u8; 4] = b"DBST";
+pub const VERSION: u8 = 3;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    Format(&'static str),
+    Version(u8),
+    RomMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "{}", e),
+            StateError::Format(msg) => write!(f, "invalid save state: {}", msg),
+            StateError::Version(version) => write!(f, "unsupported save state version {}", version),
+            StateError::RomMismatch { expected, found } => {
+                write!(f, "save state was made with ROM crc32 {:08x}, but this ROM is {:08x}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+// Serializes emulator state in little endian. Every component writes its fields in a
+// fixed order and reads them back in the same order, see `Gameboy::save_state`.
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter {
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn write_u8(&mut self, dat: u8) {
+        self.buf.push(dat);
+    }
+
+    pub fn write_bool(&mut self, dat: bool) {
+        self.buf.push(dat as u8);
+    }
+
+    pub fn write_u16(&mut self, dat: u16) {
+        self.buf.extend_from_slice(&dat.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, dat: u32) {
+        self.buf.extend_from_slice(&dat.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, dat: u64) {
+        self.buf.extend_from_slice(&dat.to_le_bytes());
+    }
+
+    // Fixed-size data, the reader has to know the length.
+    pub fn write_bytes(&mut self, dat: &[u8]) {
+        self.buf.extend_from_slice(dat);
+    }
+
+    // Variable-size data prefixed with its length.
+    pub fn write_vec(&mut self, dat: &[u8]) {
+        self.write_u32(dat.len() as u32);
+        self.write_bytes(dat);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        StateReader {
+            buf,
+            pos: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let dat = self.buf.get(self.pos..self.pos + len).ok_or(StateError::Format("truncated"))?;
+        self.pos += len;
+        Ok(dat)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Format("invalid bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let dat = self.take(2)?;
+        Ok(u16::from_le_bytes([dat[0], dat[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let dat = self.take(4)?;
+        Ok(u32::from_le_bytes([dat[0], dat[1], dat[2], dat[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut dat = [0; 8];
+        dat.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(dat))
+    }
+
+    pub fn read_bytes(&mut self, dst: &mut [u8]) -> Result<(), StateError> {
+        dst.copy_from_slice(self.take(dst.len())?);
+        Ok(())
+    }
+
+    // Reads data written by `write_vec` into a buffer that must already have the same size.
+    pub fn read_vec(&mut self, dst: &mut [u8]) -> Result<(), StateError> {
+        if self.read_u32()? as usize != dst.len() {
+            return Err(StateError::Format("size mismatch"));
+        }
+        self.read_bytes(dst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+// Writes the header that identifies the format and the ROM.
+pub fn write_header(w: &mut StateWriter, rom_crc32: u32) {
+    w.write_bytes(MAGIC);
+    w.write_u8(VERSION);
+    w.write_u32(rom_crc32);
+}
+
+// Checks the header and returns the ROM crc32 it was made with.
+pub fn read_header(r: &mut StateReader) -> Result<u32, StateError> {
+    let mut magic = [0; 4];
+    r.read_bytes(&mut magic).map_err(|_| StateError::Format("bad header"))?;
+    if &magic != MAGIC {
+        return Err(StateError::Format("bad header"));
+    }
+    let version = r.read_u8()?;
+    if version != VERSION {
+        return Err(StateError::Version(version));
+    }
+    r.read_u32()
+}
@@ -0,0 +1,157 @@
+use std::{fmt, fs, io, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}};
+
+use crate::cpu::CpuEvent;
+use crate::defs::CLOCKS_PER_FRAME;
+use crate::gameboy::Gameboy;
+
+// Frames a test ROM may run before it counts as timed out, about two minutes.
+pub const DEFAULT_TIMEOUT: u32 = 7200;
+
+// Mooneye's LD B,B breakpoint.
+const LD_B_B: u8 = 0x40;
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+// Blargg's result in cartridge RAM: status, signature, then zero-terminated text.
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Timeout,
+    // The emulator panicked.
+    Crashed,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Failed(_) => write!(f, "FAIL"),
+            Outcome::Timeout => write!(f, "TIMEOUT"),
+            Outcome::Crashed => write!(f, "CRASH"),
+        }
+    }
+}
+
+pub struct TestResult {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    pub frames: u32,
+}
+
+// Runs a Blargg or Mooneye test ROM headless until it reports a result or `timeout` frames pass.
+pub fn run(path: &Path, timeout: u32) -> TestResult {
+    let fname = path.to_string_lossy().into_owned();
+    let mut frames = 0;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut gameboy = Gameboy::headless(&fname);
+        gameboy.mmc.serial.output = Some(Vec::new());
+        let mut cycles = 0;
+        let mut serial_len = 0;
+        while frames < timeout {
+            cycles += gameboy.step();
+            if gameboy.cpu.cpu.opcode == LD_B_B {
+                let regs = &gameboy.cpu.cpu.regs;
+                if let Some(outcome) = mooneye_result([regs.b, regs.c, regs.d, regs.e, regs.h, regs.l]) {
+                    return outcome;
+                }
+            }
+            if let Some(CpuEvent::IllegalOpcode { pc, opcode }) = gameboy.poll_event() {
+                return Outcome::Failed(format!("CPU locked up: illegal opcode 0x{:02x} at 0x{:04x}", opcode, pc));
+            }
+
+            if cycles >= CLOCKS_PER_FRAME {
+                cycles -= CLOCKS_PER_FRAME;
+                frames += 1;
+                // Wait for the serial text to be complete.
+                let serial = gameboy.mmc.serial.output.as_deref().unwrap_or(&[]);
+                let len = serial.len();
+                if len == serial_len {
+                    if let Some(outcome) = blargg_result(gameboy.mmc.rom.mapper.ram(), serial) {
+                        return outcome;
+                    }
+                }
+                serial_len = len;
+            }
+        }
+        Outcome::Timeout
+    }));
+
+    TestResult {
+        path: path.to_path_buf(),
+        outcome: result.unwrap_or(Outcome::Crashed),
+        frames,
+    }
+}
+
+// Mooneye's result in B, C, D, E, H and L when it hits LD B,B.
+pub fn mooneye_result(regs: [u8; 6]) -> Option<Outcome> {
+    match regs {
+        MOONEYE_PASS => Some(Outcome::Passed),
+        MOONEYE_FAIL => Some(Outcome::Failed("registers 0x42".to_string())),
+        _ => None,
+    }
+}
+
+// Blargg's ROMs print their result over serial, the newer ones also write it to cartridge RAM.
+pub fn blargg_result(ram: &[u8], serial: &[u8]) -> Option<Outcome> {
+    if ram.len() > 4 && ram[1..4] == BLARGG_SIGNATURE && ram[0] != BLARGG_RUNNING {
+        let text = ram[4..].split(|&b| b == 0).next().unwrap_or(&[]);
+        let text = one_line(text);
+        return match ram[0] {
+            0 => Some(Outcome::Passed),
+            status => Some(Outcome::Failed(format!("status {}: {}", status, text))),
+        };
+    }
+
+    let text = one_line(serial);
+    if text.contains("Passed") {
+        Some(Outcome::Passed)
+    } else if text.contains("Failed") {
+        Some(Outcome::Failed(text))
+    } else {
+        None
+    }
+}
+
+fn one_line(text: &[u8]) -> String {
+    String::from_utf8_lossy(text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Test ROMs (.gb, .gbc) below `dir`, sorted by path.
+pub fn find_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "gb" || ext == "gbc") {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+// One line per ROM and a summary, with paths relative to `base`.
+pub fn format_table(results: &[TestResult], base: &Path) -> String {
+    let names: Vec<String> = results.iter()
+        .map(|r| r.path.strip_prefix(base).unwrap_or(&r.path).display().to_string())
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(3);
+
+    let mut table = format!("{:<width$}  {:<7}  {:>6}\n", "ROM", "RESULT", "FRAMES", width = width);
+    for (name, result) in names.iter().zip(results) {
+        table += &format!("{:<width$}  {:<7}  {:>6}", name, result.outcome.to_string(), result.frames, width = width);
+        if let Outcome::Failed(msg) = &result.outcome {
+            table += &format!("  {}", msg);
+        }
+        table += "\n";
+    }
+
+    let passed = results.iter().filter(|r| r.outcome == Outcome::Passed).count();
+    table += &format!("passed {}/{}\n", passed, results.len());
+    table
+}
@@ -1,62 +1,76 @@
-use std::{cell::RefCell, rc::Rc};
 use crate::register::ByteRegister;
+use crate::state::{StateError, StateReader, StateWriter};
 
-pub struct Clock {
-    pub period: u32,
-    pub n: u32,
-}
-
-impl Clock {
-    pub fn new(period: u32) -> Self {
-        Self {
-            period: period,
-            n: 0,
-        }
-    }
-
-    pub fn next(&mut self, cycles: u32) -> u32 {
-        self.n += cycles;
-        let s = self.n / self.period;
-        self.n = self.n % self.period;
-        s
-    }
-}
-
+#[derive(Clone)]
 pub struct Timer {
-    int_flag: Rc<RefCell<ByteRegister>>,
-    div: u8,
+    // DIV is the upper 8 bits of this 16-bit counter, which is incremented every T-cycle.
+    div_counter: u16,
     tima: u8,
     tma: u8,
     tac: u8,
-    div_clock: Clock,
-    tma_clock: Clock,
+    // TIMA overflowed during the last M-cycle and reads 0 until it is reloaded.
+    overflow: bool,
+    // TIMA was reloaded from TMA during the last M-cycle.
+    reloading: bool,
     debug: bool,
 }
 
 impl Timer {
-    pub fn new(int_flag: Rc<RefCell<ByteRegister>>) -> Self {
+    pub fn new() -> Self {
         Timer {
-            int_flag: int_flag,
-            div: 0,
+            div_counter: 0,
             tima: 0,
             tma: 0,
             tac: 0,
-            div_clock: Clock::new(256),
-            tma_clock: Clock::new(1024),
+            overflow: false,
+            reloading: false,
             debug: false,
         }
     }
 
-    pub fn set_debug(&mut self) {
-        self.debug = true;
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.div_counter);
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+        w.write_bool(self.overflow);
+        w.write_bool(self.reloading);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.div_counter = r.read_u16()?;
+        self.tima = r.read_u8()?;
+        self.tma = r.read_u8()?;
+        self.tac = r.read_u8()?;
+        self.overflow = r.read_bool()?;
+        self.reloading = r.read_bool()?;
+        Ok(())
+    }
+
+    // Sets a register without the side effects of a CPU write, e.g. when importing a state.
+    // Only the upper 8 bits of the divider are known in that case.
+    pub fn set_register(&mut self, addr: u16, dat: u8) {
+        match addr {
+            0xFF04 => self.div_counter = u16::from(dat) << 8,
+            0xFF05 => self.tima = dat,
+            0xFF06 => self.tma = dat,
+            0xFF07 => self.tac = dat & 0x07,
+            _ => panic!("Timer: Unknown address."),
+        }
+        self.overflow = false;
+        self.reloading = false;
     }
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0xFF04 => self.div,
+            0xFF04 => (self.div_counter >> 8) as u8,
             0xFF05 => self.tima,
             0xFF06 => self.tma,
-            0xFF07 => self.tac,
+            0xFF07 => self.tac | 0xF8,
             _ => panic!("Timer: Unknown address."),
         }
     }
@@ -64,50 +78,95 @@ impl Timer {
     pub fn write(&mut self, addr: u16, dat: u8) {
         match addr {
             0xFF04 => {
-                self.div = 0;
-                self.div_clock.n = 0;
-            },
-            0xFF05 => self.tima = dat,
-            0xFF06 => self.tma = dat,
-            0xFF07 => {
-                if self.tac & 0x03 != dat & 0x03 {
-                    self.tma_clock.n = 0;
-                    self.tma_clock.period = match dat & 0x03 {
-                        0b00 => 1024,
-                        0b01 => 16,
-                        0b10 => 64,
-                        0b11 => 256,
-                        _ => panic!("Never come here"),
-                    };
-                    self.tima = self.tma;
+                // Resetting the divider can produce a falling edge on the selected bit.
+                let signal = self.signal();
+                self.div_counter = 0;
+                self.detect_edge(signal);
+            }
+            0xFF05 => {
+                // A write during the reload cycle is overwritten by TMA,
+                // a write during the overflow delay cancels the reload.
+                if !self.reloading {
+                    self.tima = dat;
+                    self.overflow = false;
+                }
+            }
+            0xFF06 => {
+                self.tma = dat;
+                if self.reloading {
+                    self.tima = dat;
                 }
-                self.tac = dat;
+            }
+            0xFF07 => {
+                // Disabling the timer or switching the clock select can produce a falling edge.
+                let signal = self.signal();
+                self.tac = dat & 0x07;
+                self.detect_edge(signal);
             }
             _ => panic!("Timer: Unknown address."),
         }
     }
 
-    pub fn run(&mut self, cycles: u32) {
+    pub fn run(&mut self, cycles: u32, int_flag: &mut ByteRegister) {
         if self.debug {
-            println!("timer next div:{:x}", self.div);
+            println!("timer next div:{:x}", self.div_counter >> 8);
             println!("timer next tima:{:x}", self.tima);
             println!("timer next tma:{:x}", self.tma);
             println!("timer next tac:{:x}", self.tac);
-            println!("timer next div_clock.n:{:x}", self.div_clock.n);
-            println!("timer next tma_clock.n:{:x}", self.tma_clock.n);
+            println!("timer next div_counter:{:x}", self.div_counter);
+            println!("timer next overflow:{}", self.overflow);
         }
 
-        self.div = self.div.wrapping_add(self.div_clock.next(cycles) as u8);
+        for _ in 0..cycles / 4 {
+            self.step(int_flag);
+        }
+    }
 
-        if (self.tac & 0x04) != 0 {
-            let n = self.tma_clock.next(cycles);
-            for _ in 0..n {
-                self.tima = self.tima.wrapping_add(1);
-                if self.tima == 0 {
-                    self.tima = self.tma;
-                    self.int_flag.borrow_mut().set_bit(2, true);
-                }
-            }
+    fn step(&mut self, int_flag: &mut ByteRegister) {
+        self.reloading = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            int_flag.set_bit(2, true);
+        }
+
+        let signal = self.signal();
+        self.div_counter = self.div_counter.wrapping_add(4);
+        self.detect_edge(signal);
+    }
+
+    // The divider bit selected by TAC, ANDed with the timer enable bit.
+    fn signal(&self) -> bool {
+        if self.tac & 0x04 == 0 {
+            return false;
+        }
+
+        let bit = match self.tac & 0x03 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            0b11 => 7,
+            _ => panic!("Never come here"),
+        };
+        (self.div_counter >> bit) & 0x01 != 0
+    }
+
+    // TIMA is incremented on a falling edge of the signal.
+    fn detect_edge(&mut self, prev: bool) {
+        if !prev || self.signal() {
+            return;
         }
+
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0 {
+            self.overflow = true;
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
     }
 }
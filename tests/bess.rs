@@ -0,0 +1,72 @@
+// BESS export and import between two machines.
+
+use std::{env, fs};
+
+use deepboy::gameboy::Gameboy;
+
+// MBC1 with 64 KiB ROM and 8 KiB RAM, every bank starts with its own number.
+fn rom_file() -> String {
+    let mut rom = vec![0; 0x10000];
+    for bank in 0..4 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x134..0x13B].copy_from_slice(b"BESSTST");
+    rom[0x147] = 0x03;
+    rom[0x148] = 0x01;
+    rom[0x149] = 0x02;
+    let path = env::temp_dir().join(format!("deepboy_bess_{}.gb", std::process::id()));
+    fs::write(&path, rom).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn u32_at(data: &[u8], pos: usize) -> usize {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize
+}
+
+// Block ids in file order.
+fn block_ids(data: &[u8]) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut pos = u32_at(data, data.len() - 8);
+    while pos < data.len() - 8 {
+        ids.push(String::from_utf8_lossy(&data[pos..pos + 4]).into_owned());
+        pos += 8 + u32_at(data, pos + 4);
+    }
+    ids
+}
+
+#[test]
+fn export_then_import() {
+    let fname = rom_file();
+    let mut gameboy = Gameboy::headless(&fname);
+    let regs = &mut gameboy.cpu.cpu.regs;
+    regs.a = 0x12;
+    regs.f = 0xB0;
+    regs.b = 0x34;
+    regs.l = 0x56;
+    regs.sp = 0xDFF0;
+    regs.pc = 0x0150;
+    gameboy.mmc.wram[0x0123] = 0x9A;
+    gameboy.mmc.hram[0x10] = 0xBC;
+    gameboy.mmc.int_enable = 0x05;
+    gameboy.mmc.rom.mapper.write(0x0000, 0x0A);
+    gameboy.mmc.rom.mapper.write(0x2000, 0x03);
+    gameboy.mmc.rom.mapper.write(0xA010, 0xDE);
+
+    let data = gameboy.export_bess();
+    assert_eq!(&data[data.len() - 4..], b"BESS");
+    // CORE has to come first, only NAME may precede it.
+    assert_eq!(block_ids(&data), ["NAME", "CORE", "INFO", "MBC ", "END "]);
+
+    let mut imported = Gameboy::headless(&fname);
+    imported.import_bess(&data).unwrap();
+    fs::remove_file(&fname).unwrap();
+
+    let (a, b) = (&gameboy.cpu.cpu.regs, &imported.cpu.cpu.regs);
+    assert_eq!((a.a, a.f, a.b, a.l, a.sp, a.pc), (b.a, b.f, b.b, b.l, b.sp, b.pc));
+    assert_eq!(imported.mmc.wram[0x0123], 0x9A);
+    assert_eq!(imported.mmc.hram[0x10], 0xBC);
+    assert_eq!(imported.mmc.int_enable, 0x05);
+    assert_eq!(imported.mmc.rom.mapper.read(0x4000), 0x03);
+    assert_eq!(imported.mmc.rom.mapper.read(0xA010), 0xDE);
+    assert_eq!(imported.export_bess(), data);
+}
@@ -0,0 +1,117 @@
+// Config file parsing: bindings, settings and the errors reported for bad lines.
+
+use std::{env, fs};
+
+use deepboy::config::{Config, ConfigError, Hotkey};
+use deepboy::joypad::Button;
+use deepboy::limiter::Speed;
+use minifb::Key;
+
+fn keys_for(bindings: &[(Key, Button)], button: Button) -> Vec<Key> {
+    bindings.iter().filter(|(_, b)| *b == button).map(|(key, _)| *key).collect()
+}
+
+#[test]
+fn empty_config_keeps_defaults() {
+    let config = Config::parse("# nothing here\n\n   \n").unwrap();
+    let default = Config::default();
+    assert_eq!(config.buttons, default.buttons);
+    assert_eq!(config.hotkeys, default.hotkeys);
+    assert_eq!(config.turbo_rate, default.turbo_rate);
+}
+
+#[test]
+fn binds_buttons_and_hotkeys() {
+    let config = Config::parse("start = Enter, Z\nA = x\nturbo_b = V\npause = F1\n").unwrap();
+    assert_eq!(keys_for(&config.buttons, Button::Start), vec![Key::Enter, Key::Z]);
+    assert_eq!(keys_for(&config.buttons, Button::A), vec![Key::X]);
+    assert_eq!(keys_for(&config.turbo_buttons, Button::B), vec![Key::V]);
+    let pause: Vec<Key> = config.hotkeys.iter().filter(|(_, h)| *h == Hotkey::Pause).map(|(k, _)| *k).collect();
+    assert_eq!(pause, vec![Key::F1]);
+    // Unlisted actions keep their default keys.
+    assert_eq!(keys_for(&config.buttons, Button::Select), vec![Key::Space]);
+}
+
+#[test]
+fn empty_value_unbinds_an_action() {
+    let config = Config::parse("quit =\n").unwrap();
+    assert!(!config.hotkeys.iter().any(|(_, h)| *h == Hotkey::Quit));
+}
+
+#[test]
+fn parses_settings() {
+    let text = "turbo_rate = 4\nrun_ahead = 2\nrewind_interval = 8\nrewind_budget = 16\n\
+                fast_forward_speed = 3\nslow_motion_speed = uncapped\n";
+    let config = Config::parse(text).unwrap();
+    assert_eq!(config.turbo_rate, 4);
+    assert_eq!(config.run_ahead, 2);
+    assert_eq!(config.rewind_interval, 8);
+    assert_eq!(config.rewind_budget, 16 << 20);
+    assert_eq!(config.fast_forward_speed, Speed::Factor(3.0));
+    assert_eq!(config.slow_motion_speed, Speed::Uncapped);
+}
+
+#[test]
+fn rejects_lines_without_equals() {
+    match Config::parse("# comment\nstart Enter\n") {
+        Err(ConfigError::Syntax { line: 2, text }) => assert_eq!(text, "start Enter"),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn rejects_unknown_actions() {
+    match Config::parse("jump = Space\n") {
+        Err(ConfigError::UnknownAction { line: 1, name }) => assert_eq!(name, "jump"),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn rejects_unknown_keys() {
+    match Config::parse("a = Z\nb = X, Hyper\n") {
+        Err(ConfigError::UnknownKey { line: 2, name }) => assert_eq!(name, "Hyper"),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+}
+
+#[test]
+fn rejects_invalid_values() {
+    let cases = [
+        ("turbo_rate", "0"),
+        ("turbo_rate", "fast"),
+        ("run_ahead", "5"),
+        ("rewind_interval", "0"),
+        ("rewind_budget", "-1"),
+        ("rewind_budget", "17592186044416"),
+        ("fast_forward_speed", "0"),
+        ("slow_motion_speed", "-0.5"),
+        ("slow_motion_speed", "slow"),
+    ];
+    for (name, value) in cases.iter() {
+        match Config::parse(&format!("{} = {}\n", name, value)) {
+            Err(ConfigError::InvalidValue { line: 1, name: n, value: v }) => {
+                assert_eq!((n.as_str(), v.as_str()), (*name, *value));
+            }
+            other => panic!("{} = {}: unexpected result: {:?}", name, value, other.err()),
+        }
+    }
+}
+
+#[test]
+fn load_from_reads_a_file() {
+    let path = env::temp_dir().join(format!("deepboy_config_{}", std::process::id()));
+    fs::write(&path, "turbo_rate = 6\n").unwrap();
+    let config = Config::load_from(path.clone());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(config.unwrap().turbo_rate, 6);
+}
+
+#[test]
+fn load_from_reports_missing_files() {
+    let path = env::temp_dir().join("deepboy_config_missing");
+    match Config::load_from(path.clone()) {
+        Err(ConfigError::Io(p, _)) => assert_eq!(p, path),
+        other => panic!("unexpected result: {:?}", other.err()),
+    }
+}
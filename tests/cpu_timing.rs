@@ -0,0 +1,152 @@
+// When the CPU ticks the bus relative to its memory accesses.
+
+use deepboy::bus::{Bus, FlatBus};
+use deepboy::cpu::{CPU, CYCLES};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    Read(u16),
+    Write(u16, u8),
+    Tick(u32),
+}
+
+// Flat bus logging every access and tick in order.
+struct LogBus {
+    flat: FlatBus,
+    events: Vec<Event>,
+    // Interrupt flags raised after the given number of M-cycles, like a timer would.
+    raise: Option<(u64, u8)>,
+}
+
+impl LogBus {
+    fn new(program: &[u8]) -> Self {
+        let mut flat = FlatBus::new();
+        flat.memory[0x100..0x100 + program.len()].copy_from_slice(program);
+        LogBus { flat, events: Vec::new(), raise: None }
+    }
+}
+
+impl Bus for LogBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.events.push(Event::Read(addr));
+        self.flat.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        self.events.push(Event::Write(addr, dat));
+        self.flat.write(addr, dat);
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.events.push(Event::Tick(cycles));
+        self.flat.tick(cycles);
+        if let Some((at, flags)) = self.raise {
+            if self.flat.cycles == at * 4 {
+                self.flat.memory[0xFF0F] |= flags;
+            }
+        }
+    }
+
+    fn int_enable(&self) -> u8 {
+        self.flat.int_enable()
+    }
+
+    fn int_flag(&self) -> u8 {
+        self.flat.int_flag()
+    }
+
+    fn set_int_flag(&mut self, dat: u8) {
+        self.flat.set_int_flag(dat);
+    }
+}
+
+fn new_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.regs.pc = 0x100;
+    cpu.regs.sp = 0xD000;
+    cpu.ime = false;
+    cpu
+}
+
+#[test]
+fn every_access_takes_one_m_cycle() {
+    // LD (HL),A
+    let mut bus = LogBus::new(&[0x77]);
+    let mut cpu = new_cpu();
+    cpu.regs.set_hl(0xC000);
+    cpu.regs.a = 0x12;
+    assert_eq!(cpu.step(&mut bus), 8);
+    assert_eq!(bus.events, [Event::Read(0x100), Event::Tick(4), Event::Write(0xC000, 0x12), Event::Tick(4)]);
+
+    // PUSH BC: the internal M-cycle is ticked after the accesses.
+    let mut bus = LogBus::new(&[0xC5]);
+    let mut cpu = new_cpu();
+    cpu.regs.set_bc(0x1234);
+    assert_eq!(cpu.step(&mut bus), 16);
+    assert_eq!(bus.events, [
+        Event::Read(0x100), Event::Tick(4),
+        Event::Write(0xCFFF, 0x12), Event::Tick(4),
+        Event::Write(0xCFFE, 0x34), Event::Tick(4),
+        Event::Tick(4),
+    ]);
+}
+
+#[test]
+fn ticks_match_the_instruction_length() {
+    for opcode in 0..=0xFF_u8 {
+        let program = match opcode {
+            0xCB => vec![0xCB, 0x46],
+            _ if CYCLES[opcode as usize] == 0 => continue,
+            _ => vec![opcode],
+        };
+        let mut bus = LogBus::new(&program);
+        let mut cpu = new_cpu();
+        let cycles = cpu.step(&mut bus);
+        assert!(cycles >= 4, "opcode 0x{:02x}", opcode);
+        assert_eq!(bus.flat.cycles, u64::from(cycles), "opcode 0x{:02x}", opcode);
+        assert!(bus.events.iter().all(|e| !matches!(e, Event::Tick(n) if *n != 4)), "opcode 0x{:02x}", opcode);
+    }
+}
+
+#[test]
+fn interrupt_dispatch_ticks_every_m_cycle() {
+    let mut bus = LogBus::new(&[0x00]);
+    bus.flat.memory[0xFFFF] = 0x04;
+    bus.flat.memory[0xFF0F] = 0x04;
+    let mut cpu = new_cpu();
+    cpu.ime = true;
+    assert_eq!(cpu.step(&mut bus), 20);
+    assert_eq!(cpu.regs.pc, 0x50);
+    let stack: Vec<_> = bus.events.iter().filter(|e| matches!(e, Event::Tick(_) | Event::Write(0xCFFE..=0xCFFF, _))).collect();
+    assert_eq!(stack, [
+        &Event::Tick(4), &Event::Tick(4),
+        &Event::Write(0xCFFF, 0x01), &Event::Tick(4),
+        &Event::Write(0xCFFE, 0x00), &Event::Tick(4),
+        &Event::Tick(4),
+    ]);
+}
+
+#[test]
+fn interrupt_raised_during_dispatch_wins_by_priority() {
+    // The timer interrupt is pending, VBLANK is raised during the high byte push.
+    let mut bus = LogBus::new(&[0x00]);
+    bus.flat.memory[0xFFFF] = 0x05;
+    bus.flat.memory[0xFF0F] = 0x04;
+    bus.raise = Some((3, 0x01));
+    let mut cpu = new_cpu();
+    cpu.ime = true;
+    cpu.step(&mut bus);
+    assert_eq!(cpu.regs.pc, 0x40);
+    assert_eq!(bus.flat.memory[0xFF0F], 0x04);
+
+    // Raised after the vector is chosen, it stays pending.
+    let mut bus = LogBus::new(&[0x00]);
+    bus.flat.memory[0xFFFF] = 0x05;
+    bus.flat.memory[0xFF0F] = 0x04;
+    bus.raise = Some((4, 0x01));
+    let mut cpu = new_cpu();
+    cpu.ime = true;
+    cpu.step(&mut bus);
+    assert_eq!(cpu.regs.pc, 0x50);
+    assert_eq!(bus.flat.memory[0xFF0F], 0x01);
+}
@@ -0,0 +1,57 @@
+// Golden-frame regression tests. Each case listed in `golden.txt` below GOLDEN_DIR,
+// by default `tests/data/golden`, is rendered and compared against `<name>.png` in
+// the same directory. Actual frames and diff images of mismatches go to the target
+// directory. Set GOLDEN_BLESS=1 to (re)write the references instead. It needs the
+// ROMs, so run it with `cargo test --test golden -- --ignored`.
+
+use std::{env, path::PathBuf};
+
+use deepboy::golden;
+
+fn golden_dir() -> PathBuf {
+    match env::var_os("GOLDEN_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/golden"),
+    }
+}
+
+#[test]
+#[ignore]
+fn golden_frames() {
+    let dir = golden_dir();
+    let manifest = dir.join("golden.txt");
+    assert!(manifest.exists(), "golden: {} not found", manifest.display());
+
+    let cases = golden::load_cases(&manifest).unwrap_or_else(|e| panic!("{}: {}", manifest.display(), e));
+    let bless = env::var_os("GOLDEN_BLESS").is_some();
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+
+    let mut failed = Vec::new();
+    for case in cases.iter() {
+        let reference = dir.join(format!("{}.png", case.name));
+        let result = if bless {
+            golden::bless(case, &reference)
+        } else {
+            golden::check(case, &reference, &out_dir)
+        };
+        match result {
+            Ok(()) => println!("golden: {}: ok", case.name),
+            Err(e) => failed.push(format!("{}: {}", case.name, e)),
+        }
+    }
+    assert!(failed.is_empty(), "golden frames differ:\n{}", failed.join("\n"));
+}
+
+#[test]
+fn golden_compare_ignores_palette() {
+    // Our shades against dmg-acid2's palette.
+    let actual: Vec<u8> = [0xFF, 0xC0, 0x60, 0x00].iter().flat_map(|&v| [v; 3]).collect();
+    let mut reference: Vec<u8> = [0xFF, 0xAA, 0x55, 0x00].iter().flat_map(|&v| [v; 3]).collect();
+    assert_eq!(golden::compare(&actual, &reference), vec![false; 4]);
+    assert_eq!(golden::frame_hash(&actual), golden::frame_hash(&reference));
+
+    reference[3..6].copy_from_slice(&[0x55; 3]);
+    let mismatches = golden::compare(&actual, &reference);
+    assert_eq!(mismatches, vec![false, true, false, false]);
+    assert_eq!(&golden::diff_image(&actual, &mismatches)[3..6], &[0xFF, 0x00, 0x00]);
+}
@@ -0,0 +1,43 @@
+// Frame pacing on video deadlines or on an audio queue.
+
+use std::time::{Duration, Instant};
+
+use deepboy::limiter::{self, FrameLimiter, Pacing, Speed};
+
+const FRAMES: u32 = 6;
+
+fn run(limiter: &mut FrameLimiter) -> Duration {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        limiter.wait();
+    }
+    start.elapsed()
+}
+
+#[test]
+fn video_pacing_waits_for_each_frame() {
+    let mut limiter = FrameLimiter::new();
+    limiter.resync();
+    let frame_time = limiter.frame_time().unwrap();
+    assert!(run(&mut limiter) >= frame_time * (FRAMES - 1));
+}
+
+#[test]
+fn audio_pacing_leaves_waiting_to_the_queue() {
+    let mut limiter = FrameLimiter::new();
+    limiter.set_pacing(Pacing::Audio);
+    assert!(limiter.audio_paced());
+    let frame_time = limiter.frame_time().unwrap();
+    assert!(run(&mut limiter) < frame_time * (FRAMES - 1));
+
+    // Fast-forward and slow motion can't follow the audio device.
+    limiter.set_speed(Speed::Factor(0.5));
+    assert!(!limiter.audio_paced());
+    assert!(run(&mut limiter) >= frame_time * (FRAMES - 1));
+}
+
+#[test]
+fn frame_of_samples() {
+    assert_eq!(limiter::samples_per_frame(48000), 804);
+    assert_eq!(limiter::samples_per_frame(44100), 738);
+}
@@ -0,0 +1,110 @@
+// MBC1 banking, and the register writes and save states that restore it.
+
+use std::sync::Arc;
+
+use deepboy::mapper::{mbc1::Mbc1, Mapper};
+use deepboy::state::{StateError, StateReader, StateWriter};
+
+// Every bank starts with its own number.
+fn rom(banks: usize) -> Arc<[u8]> {
+    let mut rom = vec![0; banks * 0x4000];
+    for bank in 0..banks {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom.into()
+}
+
+fn state(rom_bank: u8, ram_bank: u8, mode: u8) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.write_bool(false);
+    w.write_u8(rom_bank);
+    w.write_u8(ram_bank);
+    w.write_u8(mode);
+    w.write_vec(&[0; 0x8000]);
+    w.into_bytes()
+}
+
+#[test]
+fn upper_bank_bits_come_from_0x4000() {
+    let mut mbc = Mbc1::new(rom(128), vec![0; 0x8000]);
+    for (upper, bank) in [(0x01, 0x21), (0x02, 0x41), (0x03, 0x61)] {
+        mbc.write(0x2000, 0x00);
+        mbc.write(0x4000, upper);
+        assert_eq!(mbc.read(0x4000), bank);
+        assert_eq!(mbc.bank(0x4000), u16::from(bank));
+    }
+
+    mbc.write(0x2000, 0x05);
+    mbc.write(0x4000, 0x02);
+    assert_eq!(mbc.read(0x4000), 0x45);
+    // ROM banking mode keeps bank 0 at 0x0000, RAM banking mode maps `upper << 5` there.
+    assert_eq!(mbc.read(0x0000), 0x00);
+    mbc.write(0x6000, 0x01);
+    assert_eq!(mbc.read(0x0000), 0x40);
+}
+
+#[test]
+fn banks_wrap_around_the_rom_size() {
+    let mut mbc = Mbc1::new(rom(16), vec![]);
+    mbc.write(0x2000, 0x13);
+    assert_eq!(mbc.read(0x4000), 0x03);
+    mbc.write(0x4000, 0x01);
+    assert_eq!(mbc.read(0x4000), 0x03);
+}
+
+#[test]
+fn bank_writes_restore_high_banks() {
+    for (lower, upper, mode) in [(0x00, 0x01, 0x00), (0x00, 0x03, 0x00), (0x1F, 0x02, 0x01)] {
+        let mut mbc = Mbc1::new(rom(128), vec![0; 0x8000]);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x2000, lower);
+        mbc.write(0x4000, upper);
+        mbc.write(0x6000, mode);
+
+        let mut restored = Mbc1::new(rom(128), vec![0; 0x8000]);
+        for (addr, dat) in mbc.bank_writes() {
+            restored.write(addr, dat);
+        }
+        for addr in [0x0000, 0x4000, 0xA000] {
+            assert_eq!(restored.bank(addr), mbc.bank(addr), "bank at 0x{:04x}", addr);
+        }
+        assert_eq!(restored.read(0x4000), mbc.read(0x4000));
+    }
+}
+
+#[test]
+fn load_state_rejects_out_of_range_banks() {
+    let mut mbc = Mbc1::new(rom(128), vec![0; 0x8000]);
+    mbc.write(0x2000, 0x05);
+    mbc.write(0x4000, 0x01);
+    for (rom_bank, ram_bank, mode) in [(0x00, 0x00, 0x00), (0x21, 0x00, 0x00), (0x01, 0x04, 0x01), (0x01, 0x00, 0x02)] {
+        let data = state(rom_bank, ram_bank, mode);
+        match mbc.load_state(&mut StateReader::new(&data)) {
+            Err(StateError::Format(_)) => {},
+            other => panic!("{:02x}/{:02x}/{:02x}: unexpected result: {:?}", rom_bank, ram_bank, mode, other),
+        }
+        // A rejected state leaves the banks alone.
+        assert_eq!(mbc.read(0x4000), 0x25);
+        assert_eq!(mbc.read(0x0000), 0x00);
+    }
+
+    let data = state(0x1F, 0x03, 0x01);
+    mbc.load_state(&mut StateReader::new(&data)).unwrap();
+    assert_eq!(mbc.read(0x4000), 0x7F);
+}
+
+#[test]
+fn missing_or_small_ram() {
+    // Blargg's ROMs enable RAM and write their signature whether the cartridge has RAM or not.
+    let mut mbc = Mbc1::new(rom(4), vec![]);
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0xA001, 0xDE);
+    assert_eq!(mbc.read(0xA001), 0xFF);
+
+    // 2 KiB RAM repeats across 0xA000-0xBFFF.
+    let mut mbc = Mbc1::new(rom(4), vec![0; 0x800]);
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0xB801, 0xB0);
+    assert_eq!(mbc.read(0xA001), 0xB0);
+    assert_eq!(mbc.ram()[0x001], 0xB0);
+}
@@ -0,0 +1,80 @@
+// PNG encoder and decoder: round trips through our own stored-block encoder, and an
+// image compressed elsewhere with dynamic Huffman codes and every filter type.
+
+use std::{env, fs};
+
+use deepboy::defs::{GAMEBOY_HEIGHT, GAMEBOY_WIDTH};
+use deepboy::png;
+
+// 16x10 RGB, pixel (x, y) = [x * 8, y * 16, x * y * 3], written by Python's zlib at level 9.
+// Row y uses filter type y % 5.
+const DYNAMIC_PNG: [u8; 283] = [
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x0a, 0x08, 0x02, 0x00, 0x00, 0x00, 0x32, 0xdc, 0x49,
+    0xcb, 0x00, 0x00, 0x00, 0xe2, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x9d, 0x91, 0xb1, 0x4a, 0xc3,
+    0x00, 0x10, 0x86, 0xbf, 0x9a, 0xa3, 0xfc, 0xc1, 0x60, 0xce, 0xd0, 0x60, 0x30, 0x58, 0x12, 0x14,
+    0x2c, 0x42, 0xa1, 0x63, 0x07, 0x87, 0x8e, 0x01, 0x97, 0x1b, 0x8b, 0x53, 0xc7, 0xba, 0x75, 0xcc,
+    0x1b, 0xf8, 0x06, 0x8e, 0x6e, 0xbe, 0x81, 0x6f, 0xe0, 0x1b, 0xf8, 0x06, 0xbe, 0x89, 0x4d, 0xc1,
+    0xc9, 0xc1, 0x22, 0x7c, 0xc3, 0x0d, 0xc7, 0x7f, 0xf7, 0xdd, 0x01, 0x08, 0x1c, 0x2a, 0x68, 0x60,
+    0x06, 0x0b, 0x58, 0xc2, 0x0a, 0x3a, 0x08, 0x58, 0xc3, 0x06, 0xb6, 0xb0, 0x83, 0x1e, 0x46, 0xfb,
+    0x5e, 0x91, 0x1c, 0xcf, 0xc9, 0x10, 0xee, 0x09, 0x3e, 0xc6, 0x53, 0x3c, 0xc3, 0x73, 0xbc, 0xc0,
+    0x4b, 0xbc, 0xc2, 0x6b, 0x7c, 0x8a, 0xb7, 0xf8, 0x0d, 0x7e, 0x8b, 0xdf, 0xe1, 0xf3, 0x64, 0xbf,
+    0x87, 0x69, 0x6c, 0x92, 0x29, 0x35, 0x9d, 0x9a, 0x32, 0xd3, 0x99, 0x29, 0x37, 0x9d, 0x9b, 0x0a,
+    0xd3, 0xc4, 0x54, 0x9a, 0x2e, 0x4c, 0x95, 0xe9, 0xd2, 0x54, 0xdb, 0x30, 0x81, 0x04, 0xc6, 0x90,
+    0x42, 0x76, 0x04, 0x81, 0x22, 0xf7, 0x98, 0x56, 0x31, 0x6f, 0xe2, 0x7e, 0x16, 0x0f, 0x8b, 0x78,
+    0x5c, 0xc6, 0xd3, 0x2a, 0xfa, 0x2e, 0x9e, 0x23, 0x5e, 0xd6, 0xf1, 0xb6, 0x89, 0xf7, 0x6d, 0x7c,
+    0xec, 0xe2, 0xb3, 0x8f, 0xaf, 0xd1, 0xfe, 0x04, 0xa2, 0x38, 0x9e, 0x7f, 0x48, 0x77, 0xfc, 0x61,
+    0xa9, 0x2b, 0xd3, 0xab, 0xa9, 0x31, 0xb5, 0xa6, 0xeb, 0xdf, 0xd2, 0x39, 0x14, 0x50, 0x1e, 0x3e,
+    0x59, 0x1f, 0x68, 0x7f, 0x8a, 0x81, 0x6f, 0xcc, 0xba, 0x21, 0xb0, 0xef, 0xda, 0xbd, 0xd4, 0x00,
+    0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+fn pattern(width: usize, height: usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            rgb.extend_from_slice(&[(x * 8) as u8, (y * 16) as u8, (x * y * 3) as u8]);
+        }
+    }
+    rgb
+}
+
+#[test]
+fn write_then_decode() {
+    // A full frame needs more than one stored block.
+    let rgb: Vec<u8> = (0..GAMEBOY_WIDTH * GAMEBOY_HEIGHT * 3).map(|i| (i * 7 % 251) as u8).collect();
+    let path = env::temp_dir().join(format!("deepboy_png_{}.png", std::process::id()));
+    png::write(&path, GAMEBOY_WIDTH, GAMEBOY_HEIGHT, &rgb).unwrap();
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let (width, height, decoded) = png::decode(&data).unwrap();
+    assert_eq!((width, height), (GAMEBOY_WIDTH, GAMEBOY_HEIGHT));
+    assert!(decoded == rgb, "decoded pixels differ");
+}
+
+#[test]
+fn encode_then_decode_small_images() {
+    for (width, height) in [(1, 1), (3, 2), (16, 10)] {
+        let rgb = pattern(width, height);
+        assert_eq!(png::decode(&png::encode(width, height, &rgb)).unwrap(), (width, height, rgb));
+    }
+}
+
+#[test]
+fn decode_dynamic_huffman() {
+    let (width, height, rgb) = png::decode(&DYNAMIC_PNG).unwrap();
+    assert_eq!((width, height), (16, 10));
+    assert_eq!(rgb, pattern(16, 10));
+}
+
+#[test]
+fn decode_rejects_broken_files() {
+    assert!(png::decode(b"GIF89a").is_err());
+    assert!(png::decode(&DYNAMIC_PNG[..100]).is_err());
+
+    let mut corrupt = DYNAMIC_PNG;
+    // Turn the first deflate block, right after the zlib header, into the reserved type 3.
+    corrupt[8 + 25 + 8 + 2] |= 0x06;
+    assert!(png::decode(&corrupt).is_err());
+}
@@ -0,0 +1,60 @@
+// Rewind history across operations that replace the machine state.
+
+use std::{env, fs, path::PathBuf};
+
+use deepboy::gameboy::Gameboy;
+
+// `reset` reloads the ROM, so the file stays until the test removes it.
+fn gameboy(name: &str) -> (Gameboy, PathBuf) {
+    let path = env::temp_dir().join(format!("deepboy_rewind_{}_{}.gb", name, std::process::id()));
+    fs::write(&path, vec![0; 0x8000]).unwrap();
+    let mut gameboy = Gameboy::headless(&path.to_string_lossy().into_owned());
+    gameboy.enable_rewind(2, 1 << 20);
+    (gameboy, path)
+}
+
+fn run_frames(gameboy: &mut Gameboy, frames: u32) {
+    for _ in 0..frames {
+        gameboy.exec_frame();
+    }
+}
+
+fn history(gameboy: &Gameboy) -> usize {
+    gameboy.rewind.as_ref().unwrap().frames()
+}
+
+#[test]
+fn replacing_the_state_clears_the_history() {
+    let (mut gameboy, path) = gameboy("load");
+    run_frames(&mut gameboy, 3);
+    let state = gameboy.save_state();
+    let bess = gameboy.export_bess();
+
+    run_frames(&mut gameboy, 5);
+    assert!(history(&gameboy) > 0);
+    gameboy.load_state(&state).unwrap();
+    assert_eq!(history(&gameboy), 0);
+
+    run_frames(&mut gameboy, 5);
+    gameboy.import_bess(&bess).unwrap();
+    assert_eq!(history(&gameboy), 0);
+
+    run_frames(&mut gameboy, 5);
+    gameboy.reset();
+    assert_eq!(history(&gameboy), 0);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rewinding_keeps_the_history() {
+    let (mut gameboy, path) = gameboy("step");
+    fs::remove_file(path).unwrap();
+    run_frames(&mut gameboy, 8);
+    let frames = history(&gameboy);
+    assert!(gameboy.rewind_frame());
+    assert_eq!(history(&gameboy), frames - 1);
+
+    // A failed load leaves the history alone too.
+    assert!(gameboy.load_state(b"DBST").is_err());
+    assert_eq!(history(&gameboy), frames - 1);
+}
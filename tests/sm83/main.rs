@@ -0,0 +1,242 @@
+// Runs the SM83 single-step test vectors (https://github.com/SingleStepTests/sm83)
+// against the CPU on a flat bus. Point SM83_TESTS at the directory holding the
+// `v1/*.json` files, by default `tests/data/sm83/v1`. The vectors aren't part of the
+// repository, so run it with `cargo test -- --ignored`.
+//
+// Each vector gives the registers and RAM before and after one instruction,
+// and one entry per M-cycle of bus activity. Only the number of M-cycles is
+// compared, the CPU doesn't perform its accesses on the exact cycle.
+
+mod json;
+
+use std::{env, fs, path::PathBuf};
+
+use deepboy::bus::{Bus, FlatBus};
+use deepboy::cpu::CPU;
+use json::Value;
+
+// Mismatches printed per opcode file.
+const MAX_REPORTS: usize = 5;
+
+// Flat bus remembering the touched addresses, so it can be cleared cheaply between vectors.
+struct TestBus {
+    flat: FlatBus,
+    touched: Vec<u16>,
+}
+
+impl Bus for TestBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.flat.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, dat: u8) {
+        self.touched.push(addr);
+        self.flat.write(addr, dat);
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.flat.tick(cycles);
+    }
+
+    fn int_enable(&self) -> u8 {
+        self.flat.int_enable()
+    }
+
+    fn int_flag(&self) -> u8 {
+        self.flat.int_flag()
+    }
+
+    fn set_int_flag(&mut self, dat: u8) {
+        self.touched.push(0xFF0F);
+        self.flat.set_int_flag(dat);
+    }
+}
+
+impl TestBus {
+    fn clear(&mut self) {
+        for addr in self.touched.drain(..) {
+            self.flat.memory[addr as usize] = 0;
+        }
+    }
+}
+
+struct CpuState {
+    regs: [(&'static str, u16); 10],
+    ime: bool,
+    ram: Vec<(u16, u8)>,
+}
+
+fn field(value: &Value, key: &str) -> Result<u64, String> {
+    value.get(key).and_then(Value::as_u64).ok_or_else(|| format!("missing field `{}`", key))
+}
+
+fn parse_state(value: &Value) -> Result<CpuState, String> {
+    let mut regs = [("", 0); 10];
+    for (reg, name) in regs.iter_mut().zip(["a", "f", "b", "c", "d", "e", "h", "l", "sp", "pc"]) {
+        *reg = (name, field(value, name)? as u16);
+    }
+
+    let mut ram = Vec::new();
+    for entry in value.get("ram").and_then(Value::as_array).ok_or("missing field `ram`")? {
+        match entry.as_array() {
+            Some([addr, dat]) => {
+                let addr = addr.as_u64().ok_or("invalid ram address")?;
+                let dat = dat.as_u64().ok_or("invalid ram value")?;
+                ram.push((addr as u16, dat as u8));
+            }
+            _ => return Err("invalid ram entry".to_string()),
+        }
+    }
+
+    Ok(CpuState {
+        regs,
+        ime: field(value, "ime")? != 0,
+        ram,
+    })
+}
+
+fn load(cpu: &mut CPU, bus: &mut TestBus, state: &CpuState) {
+    let regs = &mut cpu.regs;
+    for (name, dat) in state.regs {
+        match name {
+            "a" => regs.a = dat as u8,
+            "f" => regs.f = dat as u8,
+            "b" => regs.b = dat as u8,
+            "c" => regs.c = dat as u8,
+            "d" => regs.d = dat as u8,
+            "e" => regs.e = dat as u8,
+            "h" => regs.h = dat as u8,
+            "l" => regs.l = dat as u8,
+            "sp" => regs.sp = dat,
+            "pc" => regs.pc = dat,
+            _ => unreachable!(),
+        }
+    }
+    cpu.ime = state.ime;
+    for &(addr, dat) in state.ram.iter() {
+        bus.write(addr, dat);
+    }
+}
+
+fn flags(f: u16) -> String {
+    ["Z", "N", "H", "C"].iter().enumerate()
+        .map(|(i, name)| if f & (0x80 >> i) != 0 { *name } else { "-" })
+        .collect()
+}
+
+// Runs one vector and returns its mismatches.
+fn run_vector(cpu: &mut CPU, bus: &mut TestBus, test: &Value) -> Result<Vec<String>, String> {
+    let initial = parse_state(test.get("initial").ok_or("missing field `initial`")?)?;
+    let expected = parse_state(test.get("final").ok_or("missing field `final`")?)?;
+    let cycles = test.get("cycles").and_then(Value::as_array).ok_or("missing field `cycles`")?;
+
+    *cpu = CPU::new();
+    bus.clear();
+    load(cpu, bus, &initial);
+    let found_cycles = cpu.step(bus) / 4;
+
+    let regs = &cpu.regs;
+    let bytes = [regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+    let found = bytes.iter().map(|&r| u16::from(r)).chain([regs.sp, regs.pc]);
+
+    let mut mismatches = Vec::new();
+    for (&(name, expected), found) in expected.regs.iter().zip(found) {
+        if name == "f" && expected != found {
+            mismatches.push(format!("flags expected {} found {}", flags(expected), flags(found)));
+        } else if expected != found {
+            mismatches.push(format!("{} expected 0x{:x} found 0x{:x}", name, expected, found));
+        }
+    }
+    // An EI whose delay is still pending counts as enabled.
+    let ime = cpu.ime || cpu.ei_delay;
+    if ime != expected.ime {
+        mismatches.push(format!("ime expected {} found {}", expected.ime, ime));
+    }
+    for &(addr, dat) in expected.ram.iter() {
+        let found = bus.read(addr);
+        if found != dat {
+            mismatches.push(format!("(0x{:04x}) expected 0x{:02x} found 0x{:02x}", addr, dat, found));
+        }
+    }
+    if found_cycles as usize != cycles.len() {
+        mismatches.push(format!("cycles expected {} found {}", cycles.len(), found_cycles));
+    }
+    Ok(mismatches)
+}
+
+// Runs every vector of one opcode file and returns (passed, total).
+fn run_file(name: &str, text: &str) -> Result<(usize, usize), String> {
+    let tests = json::parse(text)?;
+    let tests = tests.as_array().ok_or("expected an array of tests")?;
+
+    let mut cpu = CPU::new();
+    let mut bus = TestBus { flat: FlatBus::new(), touched: Vec::new() };
+    let mut passed = 0;
+    let mut reported = 0;
+    for test in tests {
+        let mismatches = run_vector(&mut cpu, &mut bus, test)?;
+        if mismatches.is_empty() {
+            passed += 1;
+        } else if reported < MAX_REPORTS {
+            reported += 1;
+            let test_name = test.get("name").and_then(Value::as_str).unwrap_or("?");
+            println!("{}: {}: {}", name, test_name, mismatches.join(", "));
+        }
+    }
+    Ok((passed, tests.len()))
+}
+
+fn tests_dir() -> PathBuf {
+    match env::var_os("SM83_TESTS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/sm83/v1"),
+    }
+}
+
+#[test]
+#[ignore]
+fn sm83_vectors() {
+    let dir = tests_dir();
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    assert!(!files.is_empty(), "no SM83 test vectors in {}", dir.display());
+    files.sort();
+
+    let mut failed = Vec::new();
+    let mut total_passed = 0;
+    let mut total = 0;
+    for path in files {
+        // Files are named after the opcode, e.g. `3e.json` or `cb 1a.json`.
+        let name = path.file_stem().unwrap().to_string_lossy().to_uppercase();
+        let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let (passed, count) = run_file(&name, &text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        if passed != count {
+            failed.push(format!("{:<6} {:>5}/{}", name, passed, count));
+        }
+        total_passed += passed;
+        total += count;
+    }
+
+    println!("sm83: {}/{} vectors passed", total_passed, total);
+    assert!(failed.is_empty(), "opcodes with failing vectors:\n{}", failed.join("\n"));
+}
+
+// A hand-written vector in the same schema, so the harness itself is covered without the files.
+#[test]
+fn sm83_sample_vector() {
+    let text = r#"[{
+        "name": "80 0000",
+        "initial": {"pc": 49152, "sp": 65534, "a": 58, "b": 198, "c": 0, "d": 0, "e": 0,
+                    "f": 0, "h": 0, "l": 0, "ime": 0, "ram": [[49152, 128]]},
+        "final": {"pc": 49153, "sp": 65534, "a": 0, "b": 198, "c": 0, "d": 0, "e": 0,
+                  "f": 176, "h": 0, "l": 0, "ime": 0, "ram": [[49152, 128]]},
+        "cycles": [[49152, 128, "r-m"]]
+    }]"#;
+    assert_eq!(run_file("80", text), Ok((1, 1)));
+
+    let wrong = text.replace("\"f\": 176", "\"f\": 0");
+    assert_eq!(run_file("80", &wrong), Ok((0, 1)));
+}
@@ -0,0 +1,77 @@
+// Result detection and reporting of the test ROM runner. `test_roms` runs every Blargg
+// and Mooneye test ROM below TEST_ROMS, by default `tests/data/roms`, and prints the
+// result table. It needs the ROMs, so run it with `cargo test -- --ignored`.
+
+use std::{env, path::{Path, PathBuf}};
+
+use deepboy::testrom::{self, Outcome, TestResult};
+
+fn roms_dir() -> PathBuf {
+    match env::var_os("TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/roms"),
+    }
+}
+
+#[test]
+#[ignore]
+fn test_roms() {
+    let dir = roms_dir();
+    let roms = testrom::find_roms(&dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+    assert!(!roms.is_empty(), "no test ROMs below {}", dir.display());
+
+    let timeout = env::var("TEST_ROM_TIMEOUT").ok()
+        .map(|n| n.parse().expect("TEST_ROM_TIMEOUT must be a number of frames"))
+        .unwrap_or(testrom::DEFAULT_TIMEOUT);
+    let results: Vec<_> = roms.iter().map(|rom| testrom::run(rom, timeout)).collect();
+    println!("{}", testrom::format_table(&results, &dir));
+    assert!(results.iter().all(|r| r.outcome == Outcome::Passed), "some test ROMs did not pass");
+}
+
+#[test]
+fn mooneye_registers() {
+    assert_eq!(testrom::mooneye_result([3, 5, 8, 13, 21, 34]), Some(Outcome::Passed));
+    assert_eq!(testrom::mooneye_result([0x42; 6]), Some(Outcome::Failed("registers 0x42".to_string())));
+    assert_eq!(testrom::mooneye_result([3, 5, 8, 13, 21, 0]), None);
+}
+
+#[test]
+fn blargg_serial_text() {
+    assert_eq!(testrom::blargg_result(&[], b"cpu_instrs\n\n01:ok\n\nPassed\n"), Some(Outcome::Passed));
+    assert_eq!(
+        testrom::blargg_result(&[], b"03-op sp,hl\n\nE8 E8\n\nFailed\n"),
+        Some(Outcome::Failed("03-op sp,hl E8 E8 Failed".to_string())),
+    );
+    assert_eq!(testrom::blargg_result(&[], b"cpu_instrs\n\n01:ok  02:"), None);
+}
+
+#[test]
+fn blargg_cartridge_ram() {
+    let mut ram = vec![0x80, 0xDE, 0xB0, 0x61];
+    ram.extend_from_slice(b"mem_timing\n\nFailed #2\n\0garbage");
+    // While the status says running, only the serial text counts.
+    assert_eq!(testrom::blargg_result(&ram, b""), None);
+    assert_eq!(testrom::blargg_result(&ram, b"Passed"), Some(Outcome::Passed));
+    ram[0] = 2;
+    assert_eq!(testrom::blargg_result(&ram, b"Passed"), Some(Outcome::Failed("status 2: mem_timing Failed #2".to_string())));
+    ram[0] = 0;
+    assert_eq!(testrom::blargg_result(&ram, b""), Some(Outcome::Passed));
+}
+
+#[test]
+fn result_table() {
+    let base = Path::new("/roms");
+    let results = [
+        TestResult { path: base.join("blargg/cpu_instrs.gb"), outcome: Outcome::Passed, frames: 3120 },
+        TestResult { path: base.join("mooneye/di_timing.gb"), outcome: Outcome::Failed("registers 0x42".to_string()), frames: 12 },
+        TestResult { path: PathBuf::from("other/halt_bug.gb"), outcome: Outcome::Timeout, frames: 7200 },
+    ];
+    let expected = "\
+ROM                   RESULT   FRAMES
+blargg/cpu_instrs.gb  pass       3120
+mooneye/di_timing.gb  FAIL         12  registers 0x42
+other/halt_bug.gb     TIMEOUT    7200
+passed 1/3
+";
+    assert_eq!(testrom::format_table(&results, base), expected);
+}
@@ -0,0 +1,53 @@
+// Watchpoints only see the CPU's memory accesses, not its internal interrupt handling.
+
+use std::{env, fs};
+
+use deepboy::debugger::{WatchKind, Watchpoint};
+use deepboy::gameboy::Gameboy;
+
+// NOPs at 0x0100, then LD A,(0xFFFF) and LD (0xFF0F),A.
+fn gameboy() -> Gameboy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x110..0x116].copy_from_slice(&[0xFA, 0xFF, 0xFF, 0xEA, 0x0F, 0xFF]);
+    let path = env::temp_dir().join(format!("deepboy_watch_{}.gb", std::process::id()));
+    fs::write(&path, rom).unwrap();
+    let mut gameboy = Gameboy::headless(&path.to_string_lossy().into_owned());
+    fs::remove_file(&path).unwrap();
+
+    gameboy.mmc.rom.disable_boot_rom = 1;
+    gameboy.cpu.cpu.regs.pc = 0x0100;
+    gameboy.cpu.cpu.regs.sp = 0xFFFE;
+    gameboy.cpu.cpu.ime = true;
+    gameboy.mmc.watchpoints = vec![
+        Watchpoint { start: 0xFFFF, end: 0xFFFF, bank: None, kind: WatchKind::Read },
+        Watchpoint { start: 0xFF0F, end: 0xFF0F, bank: None, kind: WatchKind::Write },
+    ];
+    gameboy
+}
+
+#[test]
+fn interrupt_polling_is_not_watched() {
+    let mut gameboy = gameboy();
+    for _ in 0..8 {
+        gameboy.step();
+        assert_eq!(gameboy.mmc.watch_hit, None);
+    }
+
+    // Dispatching acknowledges IF without a watched write.
+    gameboy.mmc.int_enable = 0x04;
+    gameboy.mmc.int_flag.set(0x04);
+    gameboy.step();
+    assert_eq!(gameboy.cpu.cpu.interrupt, Some(0x50));
+    assert_eq!(gameboy.mmc.int_flag.get() & 0x04, 0);
+    assert_eq!(gameboy.mmc.watch_hit, None);
+}
+
+#[test]
+fn cpu_accesses_are_watched() {
+    let mut gameboy = gameboy();
+    gameboy.cpu.cpu.regs.pc = 0x0110;
+    gameboy.step();
+    assert_eq!(gameboy.mmc.watch_hit.take().map(|hit| (hit.index, hit.addr)), Some((0, 0xFFFF)));
+    gameboy.step();
+    assert_eq!(gameboy.mmc.watch_hit.take().map(|hit| (hit.index, hit.addr)), Some((1, 0xFF0F)));
+}